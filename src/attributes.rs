@@ -0,0 +1,76 @@
+/// Attributes in the pandoc style `{#id .class key=value}`, for example at the end of a heading
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attributes {
+    pub id: Option<String>,
+    pub classes: Vec<String>,
+    pub values: Vec<(String, String)>,
+}
+
+impl Attributes {
+    /// Parse the content between the braces of an attribute block. Returns `None` if any of the
+    /// entries is not a valid attribute
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut attributes = Self::default();
+
+        for entry in s.split_whitespace() {
+            if let Some(id) = entry.strip_prefix('#') {
+                attributes.id = Some(id.to_string());
+            } else if let Some(class) = entry.strip_prefix('.') {
+                attributes.classes.push(class.to_string());
+            } else if let Some((key, value)) = entry.split_once('=') {
+                let value = value.trim_matches(|c| c == '"' || c == '\'');
+                attributes.values.push((key.to_string(), value.to_string()));
+            } else {
+                return None;
+            }
+        }
+
+        Some(attributes)
+    }
+
+    /// Split an attribute block off the end of the text. Returns the remaining text and the
+    /// parsed attributes, or `None` if the text doesn't end with a valid attribute block.
+    pub fn split_trailing(text: &str) -> Option<(&str, Self)> {
+        let text = text.trim_end();
+        let inner = text.strip_suffix('}')?;
+        let start = inner.rfind('{')?;
+        let attributes = Self::parse(&inner[start + 1..])?;
+
+        Some((inner[..start].trim_end(), attributes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_entries() {
+        let attributes = Attributes::parse("#intro .unnumbered lang=de title=\"x\"").unwrap();
+        assert_eq!(attributes.id.as_deref(), Some("intro"));
+        assert_eq!(attributes.classes, vec!["unnumbered".to_string()]);
+        assert_eq!(
+            attributes.values,
+            vec![
+                ("lang".to_string(), "de".to_string()),
+                ("title".to_string(), "x".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_empty_and_invalid() {
+        assert_eq!(Attributes::parse(""), Some(Attributes::default()));
+        assert_eq!(Attributes::parse("#id word"), None);
+    }
+
+    #[test]
+    fn split_trailing_block() {
+        let (text, attributes) = Attributes::split_trailing("Heading {#id} ").unwrap();
+        assert_eq!(text, "Heading");
+        assert_eq!(attributes.id.as_deref(), Some("id"));
+
+        assert!(Attributes::split_trailing("Set {a, b}").is_none());
+        assert!(Attributes::split_trailing("No block").is_none());
+    }
+}
@@ -1,4 +1,29 @@
-use genpdf::{Document, Margins, PaperSize, SimplePageDecorator};
+use clap::ValueEnum;
+use serde::Deserialize;
+use genpdf::{style::Color, Document, Margins, Mm, PaperSize, Size};
+
+use crate::{
+    footnotes::{FootnoteDecorator, Footnotes},
+    glyph_fallback::GlyphFallback,
+    header_footer::{HeaderFooter, HeaderFooterDecorator},
+    headings::Heading,
+    language,
+    numbering::NumberingStyle,
+    title::{Author, TitleLayout},
+    tracking::{LayoutInfo, RenderTracker},
+};
+
+/// Which rule lines are drawn for tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TableRules {
+    /// No lines at all
+    None,
+    /// Horizontal lines above and below the table and below the header (like LaTeX booktabs)
+    Booktabs,
+    /// Lines around every cell
+    Grid,
+}
 
 #[derive(Debug, Clone)]
 pub struct DocumentStyle {
@@ -9,18 +34,84 @@ pub struct DocumentStyle {
     pub h4_size: u8,
     pub h5_size: u8,
     pub h6_size: u8,
+    /// Font size of the footnotes at the bottom of the page
+    pub footnote_size: u8,
 
     pub line_spacing: f64,
     pub paragraph_spacing: f64,
     pub header_spacing: f64,
 
-    pub paper_size: PaperSize,
+    /// Paper size in portrait orientation
+    pub paper_size: Size,
+    /// Rotate the paper to landscape orientation
+    pub landscape: bool,
+    /// Margins of right-hand pages. The left margin is the inner margin in the two-sided layout
     pub page_margins: Margins,
+    /// Mirror the margins and the header and footer on left-hand (even) pages for printing
+    pub two_sided: bool,
+    /// Start chapters (level 1 headings) on right-hand pages, inserting empty pages if needed
+    pub open_right: bool,
+
+    /// Number of columns the text is set in. Headings and images span all columns
+    pub columns: usize,
+    /// Space between columns in mm
+    pub column_gap: f64,
+    /// Draw a vertical line between columns
+    pub column_rule: bool,
 
     pub align_justify: bool,
+    /// Language tag of the document like `de` or `en-GB`, used for the hyphenation and stored in
+    /// the PDF
+    pub lang: Option<String>,
     pub hyphenation: Option<hyphenation::Standard>,
 
+    /// Numbering styles for ordered lists. The first entry is used for top level lists, the
+    /// second one for ordered lists nested inside of those and so on. If lists are nested deeper
+    /// than the number of styles, the styles are repeated from the start
+    pub list_numbering: Vec<NumberingStyle>,
+
+    /// Prefix headings with hierarchical section numbers ("2.3.1 Results")
+    pub heading_numbering: bool,
+    /// Heading level that gets the first number part, higher level headings are not numbered
+    pub numbering_start_level: u8,
+    /// Number all display equations, not only the math blocks marked as `math numbered`
+    pub equation_numbering: bool,
+
+    pub table_rules: TableRules,
+    /// Label that is printed above a table that is continued from the previous page
+    pub table_continued_label: Option<String>,
+
+    /// Background color of inline code spans, should be a light tint like #eeeeee
+    pub inline_code_color: Option<Color>,
+
+    /// Text color for links
+    pub link_color: Option<Color>,
+    /// Underline the text of links
+    pub link_underline: bool,
+    /// Print the URL of links in a footnote, for documents that are meant to be printed
+    pub print_link_urls: bool,
+
+    /// Headings up to this level are added to the PDF outline (bookmarks). 0 disables the outline
+    pub outline_depth: u8,
+
+    /// Headings up to this level are listed in the table of contents
+    pub toc_depth: u8,
+    pub toc_title: String,
+
     pub title: String,
+    pub subtitle: Option<String>,
+    pub authors: Vec<Author>,
+    pub date: Option<String>,
+    pub abstract_text: Option<String>,
+    pub abstract_title: String,
+    /// Layout of the title block. If not set, the title block is only printed if it was filled
+    /// from the frontmatter or the project configuration, `--title` alone only sets the metadata
+    pub title_layout: Option<TitleLayout>,
+
+    pub header: HeaderFooter,
+    pub footer: HeaderFooter,
+    /// Print the header and footer on the first page, even if it contains the title block
+    pub title_page_header: bool,
 }
 
 impl Default for DocumentStyle {
@@ -35,19 +126,62 @@ impl Default for DocumentStyle {
             h4_size: (text_size as f32 * 1.2).round() as u8,
             h5_size: (text_size as f32 * 1.0).round() as u8,
             h6_size: (text_size as f32 * 0.8).round() as u8,
+            footnote_size: (text_size as f32 * 0.8).round() as u8,
 
             // This is absolutely stupid but lines perfectly with the latex reference document
             line_spacing: 1.281,
             paragraph_spacing: 2.5,
             header_spacing: 3.0,
 
-            paper_size: PaperSize::A4,
+            paper_size: PaperSize::A4.into(),
+            landscape: false,
             page_margins: Margins::trbl(20.0, 35.5, 30.0, 42.5),
+            two_sided: false,
+            open_right: false,
+
+            columns: 1,
+            column_gap: 6.0,
+            column_rule: false,
 
             align_justify: true,
+            lang: None,
             hyphenation: None,
 
+            list_numbering: vec![
+                NumberingStyle::Decimal,
+                NumberingStyle::LowerAlpha,
+                NumberingStyle::LowerRoman,
+            ],
+
+            heading_numbering: false,
+            numbering_start_level: 1,
+            equation_numbering: false,
+
+            table_rules: TableRules::Booktabs,
+            table_continued_label: None,
+
+            inline_code_color: None,
+
+            link_color: Some(Color::Rgb(20, 60, 160)),
+            link_underline: false,
+            print_link_urls: false,
+
+            outline_depth: 3,
+
+            toc_depth: 3,
+            toc_title: "Contents".to_string(),
+
             title: String::new(),
+            subtitle: None,
+            authors: Vec::new(),
+            date: None,
+            abstract_text: None,
+            abstract_title: "Abstract".to_string(),
+            title_layout: None,
+
+            header: HeaderFooter::default(),
+            footer: HeaderFooter::default(),
+            title_page_header: true,
         }
     }
 }
@@ -65,17 +199,157 @@ impl DocumentStyle {
         }
     }
 
-    pub fn apply_base_style(&self, doc: &mut Document) {
+    pub fn get_list_numbering(&self, depth: usize) -> NumberingStyle {
+        self.list_numbering
+            .get(depth % self.list_numbering.len().max(1))
+            .copied()
+            .unwrap_or(NumberingStyle::Decimal)
+    }
+
+    /// The paper size in the selected orientation. Custom sizes keep the orientation they were
+    /// given in (`300x200` is wider than high), `landscape` turns portrait sizes sideways
+    pub fn page_size(&self) -> Size {
+        let size = self.paper_size;
+        match self.landscape && size.width < size.height {
+            true => Size::new(size.height, size.width),
+            false => size,
+        }
+    }
+
+    /// Set the document language and use its hyphenation, if there is a dictionary for it
+    pub fn set_lang(&mut self, tag: &str) {
+        self.lang = Some(tag.to_string());
+        if let Some(hyphenation) = language::load_hyphenation(tag) {
+            self.hyphenation = Some(hyphenation);
+        }
+    }
+
+    /// The layout the title block is printed with, `TitleLayout::None` if there is no title block
+    pub fn title_block_layout(&self) -> TitleLayout {
+        match self.title.is_empty() {
+            true => TitleLayout::None,
+            false => self.title_layout.unwrap_or(TitleLayout::None),
+        }
+    }
+
+    /// Check if the header or footer contain placeholders that depend on the final layout
+    pub fn header_footer_uses_layout(&self) -> bool {
+        self.header.uses_layout() || self.footer.uses_layout()
+    }
+
+    pub fn apply_base_style(
+        &self,
+        doc: &mut Document,
+        tracker: &RenderTracker,
+        footnotes: &Footnotes,
+        glyph_fallback: &GlyphFallback,
+        headings: &[Heading],
+        layout: Option<&LayoutInfo>,
+    ) {
         if let Some(hyp) = &self.hyphenation {
             doc.set_hyphenator(hyp.clone());
         }
         doc.set_font_size(self.text_size);
         doc.set_line_spacing(self.line_spacing);
-        doc.set_paper_size(self.paper_size);
+        doc.set_paper_size(self.page_size());
         doc.set_title(&self.title);
 
-        let mut deco = SimplePageDecorator::new();
-        deco.set_margins(self.page_margins);
-        doc.set_page_decorator(deco);
+        let deco = HeaderFooterDecorator::new(self, headings, layout, tracker, glyph_fallback);
+        let deco = FootnoteDecorator::new(deco, footnotes.clone(), self.footnote_size);
+        doc.set_page_decorator(tracker.decorator(deco));
+    }
+}
+
+/// Parse a paper size, either a name (A0 - A6, B4, B5, Letter, Legal, Tabloid) or a custom size
+/// in mm in the form `WxH` (e.g. `150x200`)
+pub fn parse_paper_size(s: &str) -> Result<Size, String> {
+    let name = s.trim().to_lowercase();
+    let (width, height) = match name.as_str() {
+        "a0" => (841.0, 1189.0),
+        "a1" => (594.0, 841.0),
+        "a2" => (420.0, 594.0),
+        "a3" => (297.0, 420.0),
+        "a4" => (210.0, 297.0),
+        "a5" => (148.0, 210.0),
+        "a6" => (105.0, 148.0),
+        "b4" => (250.0, 353.0),
+        "b5" => (176.0, 250.0),
+        "letter" => (215.9, 279.4),
+        "legal" => (215.9, 355.6),
+        "tabloid" => (279.4, 431.8),
+        custom => {
+            let parse = |value: &str| value.trim().trim_end_matches("mm").trim().parse::<f64>();
+            match custom.split_once('x').map(|(w, h)| (parse(w), parse(h))) {
+                Some((Ok(width), Ok(height)))
+                    if width.is_finite() && height.is_finite() && width > 0.0 && height > 0.0 =>
+                {
+                    (width, height)
+                }
+                _ => {
+                    return Err(format!(
+                        "'{}' is not a paper size name (A0 - A6, B4, B5, Letter, Legal, \
+                        Tabloid) or a size in mm in the form WxH",
+                        s
+                    ))
+                }
+            }
+        }
+    };
+
+    Ok(Size::new(Mm(width), Mm(height)))
+}
+
+/// Parse a hex color in the form `#rrggbb` (the `#` is optional)
+pub fn parse_color(s: &str) -> Result<Color, String> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(format!("'{}' is not a color in the form #rrggbb", s));
+    }
+
+    let channel = |i: usize| {
+        u8::from_str_radix(&hex[i..i + 2], 16)
+            .map_err(|_| format!("'{}' is not a color in the form #rrggbb", s))
+    };
+
+    Ok(Color::Rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paper_size_names() {
+        assert_eq!(parse_paper_size("A4"), Ok(Size::new(Mm(210.0), Mm(297.0))));
+        assert_eq!(parse_paper_size(" letter "), Ok(Size::new(Mm(215.9), Mm(279.4))));
+    }
+
+    #[test]
+    fn custom_paper_size() {
+        assert_eq!(parse_paper_size("150x200"), Ok(Size::new(Mm(150.0), Mm(200.0))));
+        assert_eq!(parse_paper_size("150mm x 200mm"), Ok(Size::new(Mm(150.0), Mm(200.0))));
+    }
+
+    #[test]
+    fn invalid_paper_size() {
+        assert!(parse_paper_size("A9").is_err());
+        assert!(parse_paper_size("0x200").is_err());
+        assert!(parse_paper_size("150x").is_err());
+        assert!(parse_paper_size("infx200").is_err());
+        assert!(parse_paper_size("150xNaN").is_err());
+    }
+
+    #[test]
+    fn page_orientation() {
+        let mut style = DocumentStyle::default();
+        style.paper_size = parse_paper_size("A4").unwrap();
+        assert_eq!(style.page_size(), Size::new(Mm(210.0), Mm(297.0)));
+        style.landscape = true;
+        assert_eq!(style.page_size(), Size::new(Mm(297.0), Mm(210.0)));
+
+        style.paper_size = parse_paper_size("300x200").unwrap();
+        assert_eq!(style.page_size(), Size::new(Mm(300.0), Mm(200.0)));
+        style.landscape = false;
+        assert_eq!(style.page_size(), Size::new(Mm(300.0), Mm(200.0)));
     }
 }
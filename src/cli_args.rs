@@ -1,15 +1,87 @@
+use std::{fs::File, io::BufReader};
+
 use clap::{Parser, ValueEnum};
-use genpdf::Mm;
+use genpdf::{style::Color, Mm, Size};
 use hyphenation::{Load, Standard};
+use serde::Deserialize;
 
-use crate::{base_style::DocumentStyle, resources};
+use crate::{
+    base_style::{parse_color, parse_paper_size, DocumentStyle, TableRules},
+    font_files::FontFiles,
+    resources,
+    title::TitleLayout,
+};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ArgHyphenationLang {
     /// German language hyphenation rules
     De,
     /// English (US) language hyphenation rules
     En,
+    /// English (GB) language hyphenation rules, requires the `hyph-en-gb` feature
+    #[serde(rename = "en-gb")]
+    EnGb,
+    /// French language hyphenation rules, requires the `hyph-fr` feature
+    Fr,
+    /// Dutch language hyphenation rules, requires the `hyph-nl` feature
+    Nl,
+    /// Spanish language hyphenation rules, requires the `hyph-es` feature
+    Es,
+}
+
+impl ArgHyphenationLang {
+    /// Load the embedded hyphenation dictionary for the language. German and English (US) are
+    /// always embedded, the other languages only if the binary was built with their feature.
+    pub fn load(self) -> Result<Standard, String> {
+        use hyphenation::Language::{EnglishUS, German1996};
+
+        let (language, resource) = match self {
+            ArgHyphenationLang::De => (German1996, resources::HYP_DE1996),
+            ArgHyphenationLang::En => (EnglishUS, resources::HYP_EN_US),
+            #[cfg(feature = "hyph-en-gb")]
+            ArgHyphenationLang::EnGb => return load_embedded(hyphenation::Language::EnglishGB),
+            #[cfg(feature = "hyph-fr")]
+            ArgHyphenationLang::Fr => return load_embedded(hyphenation::Language::French),
+            #[cfg(feature = "hyph-nl")]
+            ArgHyphenationLang::Nl => return load_embedded(hyphenation::Language::Dutch),
+            #[cfg(feature = "hyph-es")]
+            ArgHyphenationLang::Es => return load_embedded(hyphenation::Language::Spanish),
+            #[allow(unreachable_patterns)]
+            _ => {
+                let name = self.to_possible_value().unwrap().get_name().to_string();
+                return Err(format!(
+                    "The hyphenation language '{}' is not available, marktex has to be built \
+                    with the feature 'hyph-{}'",
+                    name, name
+                ));
+            }
+        };
+        let dict = resources::get_decompress(resource);
+        Ok(Standard::from_reader(language, &mut dict.as_slice()).unwrap())
+    }
+}
+
+/// Load a dictionary that is embedded by the hyphenation crate
+#[cfg(any(
+    feature = "hyph-en-gb",
+    feature = "hyph-fr",
+    feature = "hyph-nl",
+    feature = "hyph-es"
+))]
+fn load_embedded(language: hyphenation::Language) -> Result<Standard, String> {
+    Standard::from_embedded(language).map_err(|e| {
+        format!("Can't load the embedded hyphenation dictionary for {:?}: {}", language, e)
+    })
+}
+
+/// Load a hyphenation dictionary in the format of the hyphenation crate, e.g. one of the
+/// `.standard.bincode` files. The language of the dictionary is not checked
+pub fn load_hyphenation_dict(path: &str) -> Result<Standard, String> {
+    let file = File::open(path)
+        .map_err(|e| format!("Can't read hyphenation dictionary '{}': {}", path, e))?;
+    Standard::any_from_reader(&mut BufReader::new(file))
+        .map_err(|e| format!("Invalid hyphenation dictionary '{}': {}", path, e))
 }
 
 #[derive(Parser, Debug)]
@@ -19,19 +91,30 @@ pub struct CliArgs {
     #[arg()]
     pub input: String,
 
-    /// Path to the output PDF file
+    /// Path to the output PDF file. Default is the `output` of the project config, or the input
+    /// path with the extension `.pdf`
     #[arg()]
-    pub output: String,
+    pub output: Option<String>,
 
-    /// PDF file title
+    /// Name of the profile from the project config file (`marktex.toml` next to the input file)
+    /// that is applied
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// Document title, used for the PDF metadata and the title block
     #[arg(long)]
     pub title: Option<String>,
 
-    /// Page margin left in mm
+    /// Layout of the title block. Default is article if the title block is given in the
+    /// frontmatter, otherwise the title block is only printed if this is set
+    #[arg(long, value_enum)]
+    pub title_layout: Option<TitleLayout>,
+
+    /// Page margin left in mm. This is the inner margin in the two-sided layout
     #[arg(long)]
     pub margin_left: Option<f64>,
 
-    /// Page margin right in mm
+    /// Page margin right in mm. This is the outer margin in the two-sided layout
     #[arg(long)]
     pub margin_right: Option<f64>,
 
@@ -43,14 +126,138 @@ pub struct CliArgs {
     #[arg(long)]
     pub margin_bottom: Option<f64>,
 
+    /// Two-sided layout for printing: the left and right margins and the left and right part of
+    /// the header and footer are swapped on even pages
+    #[arg(long)]
+    pub two_sided: bool,
+
+    /// Start chapters (level 1 headings) on right-hand (odd) pages by inserting empty pages
+    #[arg(long)]
+    pub open_right: bool,
+
+    /// Number of columns the text is set in. Headings and images span all columns. Default is 1
+    #[arg(long)]
+    pub columns: Option<usize>,
+
+    /// Space between columns in mm
+    #[arg(long)]
+    pub column_gap: Option<f64>,
+
+    /// Draw a vertical line between columns
+    #[arg(long)]
+    pub column_rule: bool,
+
+    /// Paper size, either a name (A0 - A6, B4, B5, Letter, Legal, Tabloid) or a custom size in mm
+    /// in the form WxH (e.g. 150x200). Default is A4
+    #[arg(long, value_parser = parse_paper_size)]
+    pub paper: Option<Size>,
+
+    /// Rotate the paper to landscape orientation
+    #[arg(long)]
+    pub landscape: bool,
+
     /// Base fontsize for the text
     #[arg(long)]
     pub font_size: Option<u8>,
 
+    /// Language tag of the document like `de` or `en-GB`. It is stored in the PDF and selects the
+    /// hyphenation language, if there is a dictionary for it
+    #[arg(long)]
+    pub lang: Option<String>,
+
     /// What language to use for hyphenation. Default is no hyphenation
     #[arg(long, value_enum)]
     pub hyphenation: Option<ArgHyphenationLang>,
 
+    /// Load the hyphenation dictionary from a file instead (a `Standard` dictionary of the
+    /// hyphenation crate, like `en-us.standard.bincode`). Overrides `--hyphenation`
+    #[arg(long)]
+    pub hyphenation_dict: Option<String>,
+
+    /// Which rule lines to draw for tables. Default is booktabs
+    #[arg(long, value_enum)]
+    pub table_rules: Option<TableRules>,
+
+    /// Label that is printed above tables that are continued from the previous page, for example
+    /// "(continued)". By default no label is printed
+    #[arg(long)]
+    pub table_continued_label: Option<String>,
+
+    /// Background color for inline code spans in the form #rrggbb, e.g. a light gray like
+    /// #eeeeee. By default inline code has no background
+    #[arg(long, value_parser = parse_color)]
+    pub inline_code_color: Option<Color>,
+
+    /// Text color for links in the form #rrggbb
+    #[arg(long, value_parser = parse_color)]
+    pub link_color: Option<Color>,
+
+    /// Underline the text of links
+    #[arg(long)]
+    pub link_underline: bool,
+
+    /// Print the URL of links in a footnote, for output that is meant to be printed
+    #[arg(long)]
+    pub print_link_urls: bool,
+
+    /// Maximum heading level that is added to the PDF outline (bookmarks). Default is 3, 0
+    /// disables the outline
+    #[arg(long)]
+    pub outline_depth: Option<u8>,
+
+    /// Maximum heading level that is listed in the table of contents (inserted with `[[toc]]`).
+    /// Default is 3
+    #[arg(long)]
+    pub toc_depth: Option<u8>,
+
+    /// Title of the table of contents. Default is "Contents"
+    #[arg(long)]
+    pub toc_title: Option<String>,
+
+    /// Prefix the headings with hierarchical section numbers like "2.3.1". Headings with the
+    /// `{.unnumbered}` attribute are excluded
+    #[arg(long)]
+    pub number_headings: bool,
+
+    /// Heading level that gets the first part of the section number, for example 2 to start
+    /// numbering with the h2 headings. Default is 1
+    #[arg(long)]
+    pub numbering_start_level: Option<u8>,
+
+    /// Number all display equations, not only the math blocks with `math numbered` as info.
+    /// Equations are numbered per top level section if the headings are numbered
+    #[arg(long)]
+    pub number_equations: bool,
+
+    /// Template for the left part of the header. The templates can contain the placeholders
+    /// {page}, {pages}, {title} and {section} (the current h1 or h2 heading)
+    #[arg(long)]
+    pub header_left: Option<String>,
+
+    /// Template for the center part of the header
+    #[arg(long)]
+    pub header_center: Option<String>,
+
+    /// Template for the right part of the header
+    #[arg(long)]
+    pub header_right: Option<String>,
+
+    /// Template for the left part of the footer
+    #[arg(long)]
+    pub footer_left: Option<String>,
+
+    /// Template for the center part of the footer, for example "{page} / {pages}"
+    #[arg(long)]
+    pub footer_center: Option<String>,
+
+    /// Template for the right part of the footer
+    #[arg(long)]
+    pub footer_right: Option<String>,
+
+    /// Don't print the header and footer on the first page if it contains the title block
+    #[arg(long)]
+    pub no_title_page_header: bool,
+
     /// Print the parsed markdown nodes during mapping
     #[arg(long)]
     pub print_ast: bool,
@@ -61,20 +268,52 @@ pub struct CliArgs {
     /// room to improve.
     #[arg(long)]
     pub disable_font_subsetting: bool,
+
+    #[command(flatten)]
+    pub fonts: FontFiles,
 }
 
-impl From<&CliArgs> for DocumentStyle {
-    fn from(value: &CliArgs) -> Self {
-        let mut style = Self::default();
+impl CliArgs {
+    /// Override the style with the arguments that were given on the command line. Flags can only
+    /// enable settings, so they don't reset settings from the frontmatter
+    pub fn apply(&self, style: &mut DocumentStyle) {
+        let value = self;
 
         if let Some(title) = &value.title {
             style.title = title.clone();
         }
+        if let Some(layout) = value.title_layout {
+            style.title_layout = Some(layout);
+        }
+
+        let templates = [
+            (&value.header_left, &mut style.header.left),
+            (&value.header_center, &mut style.header.center),
+            (&value.header_right, &mut style.header.right),
+            (&value.footer_left, &mut style.footer.left),
+            (&value.footer_center, &mut style.footer.center),
+            (&value.footer_right, &mut style.footer.right),
+        ];
+        for (value, template) in templates {
+            if let Some(value) = value {
+                *template = value.clone();
+            }
+        }
+        if value.no_title_page_header {
+            style.title_page_header = false;
+        }
 
         if let Some(font_size) = value.font_size {
             style.text_size = font_size;
         }
 
+        if let Some(paper) = value.paper {
+            style.paper_size = paper;
+        }
+        if value.landscape {
+            style.landscape = true;
+        }
+
         if let Some(margin_left) = value.margin_left {
             style.page_margins.left = Mm(margin_left);
         }
@@ -88,19 +327,81 @@ impl From<&CliArgs> for DocumentStyle {
             style.page_margins.bottom = Mm(margin_bottom);
         }
 
-        let dict_de = resources::get_decompress(resources::HYP_DE1996);
-        let dict_en = resources::get_decompress(resources::HYP_EN_US);
+        if value.two_sided {
+            style.two_sided = true;
+        }
+        if value.open_right {
+            style.open_right = true;
+        }
 
-        use hyphenation::Language::{EnglishUS, German1996};
-        style.hyphenation = value.hyphenation.map(|hyp| match hyp {
-            ArgHyphenationLang::De => {
-                Standard::from_reader(German1996, &mut dict_de.as_slice()).unwrap()
-            }
-            ArgHyphenationLang::En => {
-                Standard::from_reader(EnglishUS, &mut dict_en.as_slice()).unwrap()
-            }
-        });
+        if let Some(columns) = value.columns {
+            style.columns = columns;
+        }
+        if let Some(column_gap) = value.column_gap {
+            style.column_gap = column_gap;
+        }
+        if value.column_rule {
+            style.column_rule = true;
+        }
+
+        if let Some(table_rules) = value.table_rules {
+            style.table_rules = table_rules;
+        }
+
+        if let Some(label) = &value.table_continued_label {
+            style.table_continued_label = Some(label.clone());
+        }
+
+        if let Some(color) = value.inline_code_color {
+            style.inline_code_color = Some(color);
+        }
+
+        if let Some(color) = value.link_color {
+            style.link_color = Some(color);
+        }
+        if value.link_underline {
+            style.link_underline = true;
+        }
+        if value.print_link_urls {
+            style.print_link_urls = true;
+        }
+
+        if let Some(depth) = value.outline_depth {
+            style.outline_depth = depth;
+        }
 
-        style
+        if let Some(depth) = value.toc_depth {
+            style.toc_depth = depth;
+        }
+        if let Some(title) = &value.toc_title {
+            style.toc_title = title.clone();
+        }
+
+        if value.number_headings {
+            style.heading_numbering = true;
+        }
+        if let Some(level) = value.numbering_start_level {
+            style.numbering_start_level = level;
+        }
+        if value.number_equations {
+            style.equation_numbering = true;
+        }
+
+        if let Some(lang) = &value.lang {
+            style.set_lang(lang);
+        }
+        let hyphenation = match (&value.hyphenation_dict, value.hyphenation) {
+            (Some(path), _) => Some(load_hyphenation_dict(path)),
+            (None, Some(lang)) => Some(lang.load()),
+            (None, None) => None,
+        };
+        match hyphenation {
+            Some(Ok(hyphenation)) => style.hyphenation = Some(hyphenation),
+            Some(Err(e)) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            None => (),
+        }
     }
 }
@@ -0,0 +1,248 @@
+use std::rc::Rc;
+
+use genpdf::{
+    error::Error,
+    render,
+    style::{LineStyle, Style},
+    Context, Document, Element, Mm, Position, RenderResult, Size,
+};
+
+use crate::{
+    base_style::DocumentStyle,
+    language::{Hyphenator, WithHyphenator},
+    tracking::RenderTracker,
+};
+
+/// Paragraphs that only contain this marker start a section with multiple columns. The column
+/// count can follow the marker (`:::columns 3`), the default is two columns.
+pub const COLUMNS_START: &str = ":::columns";
+
+/// Parse the column count of a `:::columns` marker. Returns `None` if the text is no columns
+/// marker, and an error if the column count is invalid
+pub fn parse_columns_marker(text: &str) -> Option<Result<usize, String>> {
+    let count = text.strip_prefix(COLUMNS_START)?;
+    if !count.is_empty() && !count.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let count = count.trim();
+    if count.is_empty() {
+        return Some(Ok(2));
+    }
+    Some(match count.parse() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("'{}' is not a valid column count", count)),
+    })
+}
+
+/// Element that flows its elements through multiple columns. The next column is started when
+/// an element doesn't fit into the current column anymore, and the next page is started after
+/// the last column. The columns are not balanced, so the last column on the last page can be
+/// shorter than the others.
+pub struct Columns {
+    elements: Vec<Box<dyn Element>>,
+    count: usize,
+    gap: Mm,
+    rule: bool,
+    tracker: RenderTracker,
+    /// The element that is rendered next
+    index: usize,
+}
+
+impl Columns {
+    pub fn new(count: usize, gap: Mm, rule: bool, tracker: &RenderTracker) -> Self {
+        Self {
+            elements: Vec::new(),
+            count,
+            gap,
+            rule,
+            tracker: tracker.clone(),
+            index: 0,
+        }
+    }
+
+    pub fn push(&mut self, element: impl Element + 'static) {
+        self.elements.push(Box::new(element));
+    }
+}
+
+impl Element for Columns {
+    fn render(
+        &mut self,
+        context: &Context,
+        area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        let width = area.size().width;
+        let column_width = (width - self.gap * (self.count - 1) as f64) / self.count as f64;
+        let column_offset = column_width + self.gap;
+
+        let mut height = Mm(0.0);
+        let mut used_columns = 0;
+
+        for column in 0..self.count {
+            if self.index >= self.elements.len() {
+                break;
+            }
+            used_columns += 1;
+
+            let mut column_area = area.clone();
+            column_area.add_offset(Position::new(column_offset * column as f64, 0));
+            column_area.set_width(column_width);
+            // Tracked elements in the column need to know how far the column is from the right
+            // edge of the content
+            let _inset = self
+                .tracker
+                .inset_right(column_offset * (self.count - 1 - column) as f64);
+
+            let mut column_height = Mm(0.0);
+            while let Some(element) = self.elements.get_mut(self.index) {
+                let mut element_area = column_area.clone();
+                element_area.add_offset(Position::new(0, column_height));
+                let result = element.render(context, element_area, style)?;
+                column_height += result.size.height;
+
+                if result.has_more {
+                    break;
+                }
+                self.index += 1;
+            }
+
+            if column_height > height {
+                height = column_height;
+            }
+        }
+
+        if self.rule {
+            for column in 1..used_columns {
+                let x = column_offset * column as f64 - self.gap / 2.0;
+                area.draw_line(
+                    vec![Position::new(x, 0), Position::new(x, height)],
+                    LineStyle::new().with_thickness(0.1),
+                );
+            }
+        }
+
+        Ok(RenderResult {
+            size: Size::new(width, height),
+            has_more: self.index < self.elements.len(),
+        })
+    }
+}
+
+/// Places the content either directly in the document, or in columns if multiple columns are
+/// active. Elements that span all columns (like headings and images) end the current group of
+/// columns, and the following content is placed in a new group below them.
+///
+/// Inside of blocks in another language than the document, the elements are rendered with the
+/// hyphenator of that language.
+pub struct ColumnFlow {
+    /// Column count of the document, followed by the ones of the open `:::columns` sections
+    counts: Vec<usize>,
+    gap: Mm,
+    rule: bool,
+    tracker: RenderTracker,
+    current: Option<Columns>,
+    /// Hyphenator of the current language block, `None` outside of language blocks
+    block_hyphenator: Option<Rc<Hyphenator>>,
+}
+
+impl ColumnFlow {
+    pub fn new(docstyle: &DocumentStyle, tracker: &RenderTracker) -> Self {
+        Self {
+            counts: vec![docstyle.columns.max(1)],
+            gap: Mm(docstyle.column_gap),
+            rule: docstyle.column_rule,
+            tracker: tracker.clone(),
+            current: None,
+            block_hyphenator: None,
+        }
+    }
+
+    /// Set the hyphenator of the language block that starts now, `None` if the following
+    /// content is in the document language
+    pub fn set_block_hyphenator(&mut self, hyphenator: Option<Rc<Hyphenator>>) {
+        self.block_hyphenator = hyphenator;
+    }
+
+    /// Push an element that flows through the columns
+    pub fn push(&mut self, doc: &mut Document, element: impl Element + 'static) {
+        match self.block_hyphenator.clone() {
+            Some(hyphenator) => {
+                let element = WithHyphenator::new(element, hyphenator, &self.tracker);
+                self.push_flowing(doc, element)
+            }
+            None => self.push_flowing(doc, element),
+        }
+    }
+
+    /// Push an element that spans all columns
+    pub fn push_spanning(&mut self, doc: &mut Document, element: impl Element + 'static) {
+        self.finish(doc);
+        match self.block_hyphenator.clone() {
+            Some(hyphenator) => {
+                doc.push(WithHyphenator::new(element, hyphenator, &self.tracker))
+            }
+            None => doc.push(element),
+        }
+    }
+
+    fn push_flowing(&mut self, doc: &mut Document, element: impl Element + 'static) {
+        let count = *self.counts.last().unwrap();
+        if count <= 1 {
+            doc.push(element);
+            return;
+        }
+
+        let (gap, rule, tracker) = (self.gap, self.rule, &self.tracker);
+        self.current
+            .get_or_insert_with(|| Columns::new(count, gap, rule, tracker))
+            .push(element);
+    }
+
+    /// Start a `:::columns` section with the given column count
+    pub fn start_section(&mut self, doc: &mut Document, count: usize) {
+        self.finish(doc);
+        self.counts.push(count);
+    }
+
+    pub fn end_section(&mut self, doc: &mut Document) {
+        self.finish(doc);
+        if self.counts.len() > 1 {
+            self.counts.pop();
+        }
+    }
+
+    /// Push the current group of columns to the document
+    pub fn finish(&mut self, doc: &mut Document) {
+        if let Some(columns) = self.current.take() {
+            doc.push(columns);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_marker() {
+        assert_eq!(parse_columns_marker(":::columns"), Some(Ok(2)));
+        assert_eq!(parse_columns_marker(":::columns 3"), Some(Ok(3)));
+        assert_eq!(parse_columns_marker(":::columns  4 "), Some(Ok(4)));
+    }
+
+    #[test]
+    fn invalid_column_count() {
+        assert!(matches!(parse_columns_marker(":::columns 0"), Some(Err(_))));
+        assert!(matches!(parse_columns_marker(":::columns x"), Some(Err(_))));
+        assert!(matches!(parse_columns_marker(":::columns -1"), Some(Err(_))));
+    }
+
+    #[test]
+    fn other_markers() {
+        assert_eq!(parse_columns_marker(":::landscape"), None);
+        assert_eq!(parse_columns_marker(":::columnsfoo"), None);
+        assert_eq!(parse_columns_marker("columns 2"), None);
+    }
+}
@@ -0,0 +1,671 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use comrak::nodes::{AstNode, NodeValue};
+use genpdf::Mm;
+use serde::{de::IgnoredAny, Deserialize};
+
+use crate::{
+    base_style::{parse_color, parse_paper_size, DocumentStyle, TableRules},
+    cli_args::{load_hyphenation_dict, ArgHyphenationLang, CliArgs},
+    font_files::FontFiles,
+    header_footer::HeaderFooter,
+    title::{Author, TitleLayout},
+};
+
+/// A value that can be given either once or as a list
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T: Clone> OneOrMany<T> {
+    fn to_vec(&self) -> Vec<T> {
+        match self {
+            OneOrMany::One(value) => vec![value.clone()],
+            OneOrMany::Many(values) => values.clone(),
+        }
+    }
+}
+
+/// A date that is given as text or as a bare number like a year
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum DateValue {
+    Text(String),
+    Integer(i64),
+    Float(f64),
+}
+
+impl std::fmt::Display for DateValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DateValue::Text(text) => write!(f, "{}", text),
+            DateValue::Integer(number) => write!(f, "{}", number),
+            DateValue::Float(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+/// Style settings that can be set in the YAML frontmatter of the document. The keys match the
+/// long names of the command line arguments (`font-size: 12`). Arguments that are given on the
+/// command line override these settings.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StyleConfig {
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    /// A single author or a list of authors
+    pub author: Option<OneOrMany<Author>>,
+    /// A date text or a bare year (`date: 2024`)
+    pub date: Option<DateValue>,
+    #[serde(rename = "abstract")]
+    pub abstract_text: Option<String>,
+    pub abstract_title: Option<String>,
+    pub title_layout: Option<TitleLayout>,
+
+    /// Templates for the running header and footer, see [`HeaderFooter`]
+    pub header_left: Option<String>,
+    pub header_center: Option<String>,
+    pub header_right: Option<String>,
+    pub footer_left: Option<String>,
+    pub footer_center: Option<String>,
+    pub footer_right: Option<String>,
+    /// Print the header and footer on the title page
+    pub title_page_header: Option<bool>,
+
+    pub font_size: Option<u8>,
+    pub margin_left: Option<f64>,
+    pub margin_right: Option<f64>,
+    pub margin_top: Option<f64>,
+    pub margin_bottom: Option<f64>,
+    /// Mirror the margins, header and footer on even pages
+    pub two_sided: Option<bool>,
+    /// Start chapters on right-hand pages
+    pub open_right: Option<bool>,
+    /// Number of columns the text is set in
+    pub columns: Option<usize>,
+    /// Space between columns in mm
+    pub column_gap: Option<f64>,
+    /// Draw a vertical line between columns
+    pub column_rule: Option<bool>,
+    /// Paper size name or custom size in mm (`150x200`), see [`parse_paper_size`]
+    #[serde(alias = "paper-size")]
+    pub paper: Option<String>,
+    pub landscape: Option<bool>,
+
+    pub line_spacing: Option<f64>,
+    /// Space below paragraphs in mm
+    pub paragraph_spacing: Option<f64>,
+    /// Space above and below headings in mm
+    pub heading_spacing: Option<f64>,
+    /// Justify the text, otherwise the text is left aligned
+    pub justify: Option<bool>,
+    /// Language tag of the document like `de` or `en-GB`
+    pub lang: Option<String>,
+    pub hyphenation: Option<ArgHyphenationLang>,
+    /// Hyphenation dictionary file, see `--hyphenation-dict`. Paths in the project config are
+    /// relative to the config file
+    pub hyphenation_dict: Option<String>,
+
+    pub table_rules: Option<TableRules>,
+    pub table_continued_label: Option<String>,
+    pub inline_code_color: Option<String>,
+    pub link_color: Option<String>,
+    pub link_underline: Option<bool>,
+    pub print_link_urls: Option<bool>,
+    pub outline_depth: Option<u8>,
+    pub toc_depth: Option<u8>,
+    pub toc_title: Option<String>,
+    pub number_headings: Option<bool>,
+    pub numbering_start_level: Option<u8>,
+    pub number_equations: Option<bool>,
+
+    /// All keys that are not supported, only collected to warn about them
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+/// The frontmatter block of the parsed document, including the `---` delimiters
+fn document_frontmatter<'a>(root: &'a AstNode<'a>) -> Option<String> {
+    root.children().find_map(|node| match &node.data.borrow().value {
+        NodeValue::FrontMatter(fm) => Some(String::from_utf8_lossy(fm).to_string()),
+        _ => None,
+    })
+}
+
+/// The YAML between the `---` delimiters of a frontmatter block
+fn frontmatter_yaml(frontmatter: &str) -> &str {
+    let yaml = frontmatter.trim();
+    let yaml = yaml.strip_prefix("---").unwrap_or(yaml);
+    yaml.strip_suffix("---").unwrap_or(yaml)
+}
+
+/// Whether a `---` block at the start of the document is a frontmatter, which is the case if it
+/// is empty or a YAML mapping. Other blocks (like `---`, a paragraph and `---`) are markdown.
+pub fn is_frontmatter(block: &str) -> bool {
+    let yaml = frontmatter_yaml(block);
+    yaml.trim().is_empty() || serde_yaml::from_str::<serde_yaml::Mapping>(yaml).is_ok()
+}
+
+/// Whether comrak took a block for the frontmatter that is no frontmatter, see
+/// [`is_frontmatter`]. The document has to be parsed again without the frontmatter extension.
+pub fn has_invalid_frontmatter<'a>(root: &'a AstNode<'a>) -> bool {
+    document_frontmatter(root)
+        .map(|frontmatter| !is_frontmatter(&frontmatter))
+        .unwrap_or(false)
+}
+
+impl StyleConfig {
+    /// Read the config from the frontmatter of the document, if there is one. Errors and unknown
+    /// keys are reported
+    pub fn from_document<'a>(root: &'a AstNode<'a>) -> Option<Self> {
+        let frontmatter = document_frontmatter(root)?;
+
+        match Self::from_frontmatter(&frontmatter) {
+            Ok(config) => {
+                config.warn_unknown_keys("the frontmatter");
+                Some(config)
+            }
+            Err(e) => {
+                eprintln!("Error while parsing the frontmatter: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Parse the YAML frontmatter block as it is stored by comrak, including the `---` delimiters
+    fn from_frontmatter(frontmatter: &str) -> Result<Self, serde_yaml::Error> {
+        let yaml = frontmatter_yaml(frontmatter);
+
+        if yaml.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        // Every key is checked on its own, so that an invalid value only drops its own setting
+        // instead of the whole frontmatter
+        let mapping: serde_yaml::Mapping = serde_yaml::from_str(yaml)?;
+        let valid = mapping
+            .into_iter()
+            .filter(|(key, value)| {
+                let mut single = serde_yaml::Mapping::new();
+                single.insert(key.clone(), value.clone());
+                match serde_yaml::from_value::<Self>(serde_yaml::Value::Mapping(single)) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        let name = match key.as_str() {
+                            Some(name) => name.to_string(),
+                            None => format!("{:?}", key),
+                        };
+                        eprintln!("Error in the frontmatter, '{}' is ignored: {}", name, e);
+                        false
+                    }
+                }
+            })
+            .collect();
+        serde_yaml::from_value(serde_yaml::Value::Mapping(valid))
+    }
+
+    /// Check if any of the fields that are printed in the title block are set
+    fn has_title_block(&self) -> bool {
+        self.title.is_some()
+            || self.subtitle.is_some()
+            || self.author.is_some()
+            || self.date.is_some()
+            || self.abstract_text.is_some()
+    }
+
+    /// Resolve the relative paths of the settings from the given directory
+    fn relative_to(&mut self, dir: &Path) {
+        if let Some(path) = &mut self.hyphenation_dict {
+            *path = dir.join(path.as_str()).to_string_lossy().to_string();
+        }
+    }
+
+    /// Print a warning for every key that is not supported
+    pub fn warn_unknown_keys(&self, source: &str) {
+        for key in self.unknown.keys() {
+            eprintln!("Warning: Unknown key '{}' in {}", key, source);
+        }
+    }
+
+    /// Apply all settings that are set to the style. Invalid values are reported and ignored
+    pub fn apply(&self, style: &mut DocumentStyle) {
+        if let Some(title) = &self.title {
+            style.title = title.clone();
+        }
+        if let Some(subtitle) = &self.subtitle {
+            style.subtitle = Some(subtitle.clone());
+        }
+        if let Some(author) = &self.author {
+            style.authors = author.to_vec();
+        }
+        if let Some(date) = &self.date {
+            style.date = Some(date.to_string());
+        }
+        if let Some(text) = &self.abstract_text {
+            style.abstract_text = Some(text.clone());
+        }
+        if let Some(title) = &self.abstract_title {
+            style.abstract_title = title.clone();
+        }
+        if self.has_title_block() && style.title_layout.is_none() {
+            style.title_layout = Some(TitleLayout::Article);
+        }
+        if let Some(layout) = self.title_layout {
+            style.title_layout = Some(layout);
+        }
+
+        let templates = [
+            (&self.header_left, &mut style.header.left),
+            (&self.header_center, &mut style.header.center),
+            (&self.header_right, &mut style.header.right),
+            (&self.footer_left, &mut style.footer.left),
+            (&self.footer_center, &mut style.footer.center),
+            (&self.footer_right, &mut style.footer.right),
+        ];
+        for (value, template) in templates {
+            if let Some(value) = value {
+                *template = value.clone();
+            }
+        }
+        if let Some(title_page_header) = self.title_page_header {
+            style.title_page_header = title_page_header;
+        }
+
+        if let Some(font_size) = self.font_size {
+            style.text_size = font_size;
+        }
+        if let Some(margin_left) = self.margin_left {
+            style.page_margins.left = Mm(margin_left);
+        }
+        if let Some(margin_right) = self.margin_right {
+            style.page_margins.right = Mm(margin_right);
+        }
+        if let Some(margin_top) = self.margin_top {
+            style.page_margins.top = Mm(margin_top);
+        }
+        if let Some(margin_bottom) = self.margin_bottom {
+            style.page_margins.bottom = Mm(margin_bottom);
+        }
+        if let Some(two_sided) = self.two_sided {
+            style.two_sided = two_sided;
+        }
+        if let Some(open_right) = self.open_right {
+            style.open_right = open_right;
+        }
+        if let Some(columns) = self.columns {
+            style.columns = columns;
+        }
+        if let Some(column_gap) = self.column_gap {
+            style.column_gap = column_gap;
+        }
+        if let Some(column_rule) = self.column_rule {
+            style.column_rule = column_rule;
+        }
+        if let Some(paper) = &self.paper {
+            match parse_paper_size(paper) {
+                Ok(paper_size) => style.paper_size = paper_size,
+                Err(e) => eprintln!("Invalid value for 'paper': {}", e),
+            }
+        }
+        if let Some(landscape) = self.landscape {
+            style.landscape = landscape;
+        }
+
+        if let Some(line_spacing) = self.line_spacing {
+            style.line_spacing = line_spacing;
+        }
+        if let Some(spacing) = self.paragraph_spacing {
+            style.paragraph_spacing = spacing;
+        }
+        if let Some(spacing) = self.heading_spacing {
+            style.header_spacing = spacing;
+        }
+        if let Some(justify) = self.justify {
+            style.align_justify = justify;
+        }
+        if let Some(lang) = &self.lang {
+            style.set_lang(lang);
+        }
+        if let Some(lang) = self.hyphenation {
+            match lang.load() {
+                Ok(hyphenation) => style.hyphenation = Some(hyphenation),
+                Err(e) => eprintln!("Invalid value for 'hyphenation': {}", e),
+            }
+        }
+        if let Some(path) = &self.hyphenation_dict {
+            match load_hyphenation_dict(path) {
+                Ok(hyphenation) => style.hyphenation = Some(hyphenation),
+                Err(e) => eprintln!("Invalid value for 'hyphenation-dict': {}", e),
+            }
+        }
+
+        if let Some(table_rules) = self.table_rules {
+            style.table_rules = table_rules;
+        }
+        if let Some(label) = &self.table_continued_label {
+            style.table_continued_label = Some(label.clone());
+        }
+        if let Some(color) = &self.inline_code_color {
+            match parse_color(color) {
+                Ok(color) => style.inline_code_color = Some(color),
+                Err(e) => eprintln!("Invalid value for 'inline-code-color': {}", e),
+            }
+        }
+        if let Some(color) = &self.link_color {
+            match parse_color(color) {
+                Ok(color) => style.link_color = Some(color),
+                Err(e) => eprintln!("Invalid value for 'link-color': {}", e),
+            }
+        }
+        if let Some(link_underline) = self.link_underline {
+            style.link_underline = link_underline;
+        }
+        if let Some(print_link_urls) = self.print_link_urls {
+            style.print_link_urls = print_link_urls;
+        }
+
+        if let Some(depth) = self.outline_depth {
+            style.outline_depth = depth;
+        }
+        if let Some(depth) = self.toc_depth {
+            style.toc_depth = depth;
+        }
+        if let Some(title) = &self.toc_title {
+            style.toc_title = title.clone();
+        }
+
+        if let Some(number_headings) = self.number_headings {
+            style.heading_numbering = number_headings;
+        }
+        if let Some(level) = self.numbering_start_level {
+            style.numbering_start_level = level;
+        }
+        if let Some(number_equations) = self.number_equations {
+            style.equation_numbering = number_equations;
+        }
+    }
+}
+
+/// Name of the project config file that is searched next to the input file
+pub const PROJECT_CONFIG_FILE: &str = "marktex.toml";
+
+/// Settings of the project config file or one of its profiles
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ProjectSettings {
+    /// Output file, relative to the config file
+    output: Option<String>,
+    disable_font_subsetting: Option<bool>,
+    /// Font files, relative to the config file. This has to be flattened before the style, since
+    /// the style collects all unknown keys
+    #[serde(flatten)]
+    fonts: FontFiles,
+
+    #[serde(flatten)]
+    style: StyleConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProjectConfigFile {
+    #[serde(flatten)]
+    base: ProjectSettings,
+    /// Named profiles (`[profile.print]`) that override the base settings
+    #[serde(default)]
+    profile: BTreeMap<String, ProjectSettings>,
+}
+
+/// The project config file (`marktex.toml`) with the base settings and the settings of the
+/// selected profile. The style settings use the same keys as the frontmatter.
+///
+/// The style is applied in the order: base settings, frontmatter, profile, command line
+/// arguments. So the project settings are defaults for all documents and a profile that is
+/// selected on the command line overrides the frontmatter.
+#[derive(Debug)]
+pub struct ProjectConfig {
+    /// Directory of the config file, relative paths are resolved from here
+    dir: PathBuf,
+    base: ProjectSettings,
+    profile: Option<ProjectSettings>,
+}
+
+impl ProjectConfig {
+    /// Load the project config file from the directory of the input file. A selected profile must
+    /// exist in the config file.
+    pub fn load(input: &Path, profile: Option<&str>) -> Result<Option<Self>, String> {
+        let dir = input.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        let path = dir.join(PROJECT_CONFIG_FILE);
+
+        match std::fs::read_to_string(&path) {
+            Ok(content) => Self::parse(dir, &content, profile).map(Some),
+            Err(_) => match profile {
+                Some(name) => Err(format!(
+                    "The profile '{}' was selected, but there is no {} next to the input file",
+                    name, PROJECT_CONFIG_FILE
+                )),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Parse the content of the config file in the directory and select the profile
+    fn parse(dir: PathBuf, content: &str, profile: Option<&str>) -> Result<Self, String> {
+        let path = dir.join(PROJECT_CONFIG_FILE);
+        let mut file: ProjectConfigFile = toml::from_str(content)
+            .map_err(|e| format!("Error while parsing '{}': {}", path.display(), e))?;
+        file.base.style.warn_unknown_keys(PROJECT_CONFIG_FILE);
+        file.base.style.relative_to(&dir);
+
+        let profile = match profile {
+            Some(name) => {
+                let mut profile = file.profile.remove(name).ok_or_else(|| {
+                    let available: Vec<_> = file.profile.keys().map(String::as_str).collect();
+                    format!(
+                        "Unknown profile '{}', the profiles in {} are: {}",
+                        name,
+                        PROJECT_CONFIG_FILE,
+                        available.join(", ")
+                    )
+                })?;
+                let source = format!("profile '{}' of {}", name, PROJECT_CONFIG_FILE);
+                profile.style.warn_unknown_keys(&source);
+                profile.style.relative_to(&dir);
+                Some(profile)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            dir,
+            base: file.base,
+            profile,
+        })
+    }
+
+    /// The value of the setting from the profile, or from the base settings
+    fn setting<T>(&self, get: impl Fn(&ProjectSettings) -> Option<T>) -> Option<T> {
+        self.profile.as_ref().and_then(&get).or_else(|| get(&self.base))
+    }
+
+    pub fn output(&self) -> Option<PathBuf> {
+        self.setting(|settings| settings.output.as_ref().map(|output| self.dir.join(output)))
+    }
+
+    pub fn disable_font_subsetting(&self) -> bool {
+        self.setting(|settings| settings.disable_font_subsetting).unwrap_or(false)
+    }
+
+    /// The font files of the profile, falling back to the ones of the base settings
+    pub fn fonts(&self) -> FontFiles {
+        let base = self.base.fonts.clone();
+        let fonts = match &self.profile {
+            Some(profile) => profile.fonts.clone().or(base),
+            None => base,
+        };
+        fonts.relative_to(&self.dir)
+    }
+
+    /// Apply the base style settings, that are overridden by the frontmatter
+    pub fn apply_base(&self, style: &mut DocumentStyle) {
+        self.base.style.apply(style);
+    }
+
+    /// Apply the style settings of the selected profile, that override the frontmatter
+    pub fn apply_profile(&self, style: &mut DocumentStyle) {
+        if let Some(profile) = &self.profile {
+            profile.style.apply(style);
+        }
+    }
+}
+
+/// The document style with all settings applied in the order described at [`ProjectConfig`]
+pub fn document_style(
+    project: Option<&ProjectConfig>,
+    frontmatter: Option<&StyleConfig>,
+    cli_args: &CliArgs,
+) -> DocumentStyle {
+    let mut style = DocumentStyle::default();
+    if let Some(project) = project {
+        project.apply_base(&mut style);
+    }
+    if let Some(frontmatter) = frontmatter {
+        frontmatter.apply(&mut style);
+    }
+    if let Some(project) = project {
+        project.apply_profile(&mut style);
+    }
+    cli_args.apply(&mut style);
+    style
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn invalid_value_only_drops_its_key() {
+        let config =
+            StyleConfig::from_frontmatter("---\ntitle: Thesis\nfont-size: big\ndate: 2024\n---")
+                .unwrap();
+        assert_eq!(config.title.as_deref(), Some("Thesis"));
+        assert_eq!(config.font_size, None);
+        assert_eq!(config.date.map(|date| date.to_string()).as_deref(), Some("2024"));
+    }
+
+    #[test]
+    fn empty_frontmatter() {
+        let config = StyleConfig::from_frontmatter("---\n---").unwrap();
+        assert!(config.title.is_none());
+    }
+
+    #[test]
+    fn frontmatter_must_be_mapping() {
+        assert!(is_frontmatter("---\ntitle: Thesis\n---\n"));
+        assert!(is_frontmatter("---\n---\n"));
+        assert!(!is_frontmatter("---\nJust a paragraph\n---\n"));
+        assert!(!is_frontmatter("---\n- a list\n---\n"));
+        assert!(!is_frontmatter("---\ntitle: [unclosed\n---\n"));
+    }
+
+    const PROJECT: &str = r#"
+output = "out/thesis.pdf"
+font-regular = "fonts/Regular.ttf"
+font-size = 10
+line-spacing = 1.5
+title = "Base"
+justify = true
+
+[profile.print]
+font-size = 12
+paper = "A5"
+
+[profile.draft]
+output = "draft.pdf"
+"#;
+
+    fn project(profile: Option<&str>) -> Result<ProjectConfig, String> {
+        ProjectConfig::parse(PathBuf::from("docs"), PROJECT, profile)
+    }
+
+    fn path(path: &str) -> String {
+        Path::new("docs").join(path).to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn selects_profile() {
+        let base = project(None).unwrap();
+        assert!(base.profile.is_none());
+        assert_eq!(base.output(), Some(PathBuf::from(path("out/thesis.pdf"))));
+
+        let draft = project(Some("draft")).unwrap();
+        assert_eq!(draft.output(), Some(PathBuf::from(path("draft.pdf"))));
+        // Settings that the profile doesn't set are taken from the base settings
+        assert_eq!(draft.fonts().font_regular, Some(path("fonts/Regular.ttf")));
+        assert_eq!(project(Some("print")).unwrap().output(), base.output());
+    }
+
+    #[test]
+    fn unknown_profile() {
+        let error = project(Some("web")).unwrap_err();
+        assert!(error.contains("Unknown profile 'web'"), "{}", error);
+        assert!(error.contains("draft, print"), "{}", error);
+    }
+
+    #[test]
+    fn profile_overrides_base() {
+        let mut style = DocumentStyle::default();
+        let print = project(Some("print")).unwrap();
+        print.apply_base(&mut style);
+        print.apply_profile(&mut style);
+
+        assert_eq!(style.text_size, 12);
+        assert_eq!(style.paper_size, parse_paper_size("A5").unwrap());
+        assert_eq!(style.title, "Base");
+        assert!(style.align_justify);
+    }
+
+    #[test]
+    fn settings_precedence() {
+        let print = project(Some("print")).unwrap();
+        let frontmatter = StyleConfig::from_frontmatter(
+            "---\ntitle: Frontmatter\nfont-size: 11\nline-spacing: 2\n---",
+        )
+        .unwrap();
+        let cli_args = CliArgs::parse_from(["marktex", "in.md"]);
+
+        // Base < frontmatter < profile
+        let style = document_style(Some(&print), Some(&frontmatter), &cli_args);
+        assert_eq!(style.line_spacing, 2.0);
+        assert_eq!(style.title, "Frontmatter");
+        assert_eq!(style.text_size, 12);
+
+        // Profile < command line
+        let cli_args =
+            CliArgs::parse_from(["marktex", "in.md", "--font-size", "9", "--title", "Cli"]);
+        let style = document_style(Some(&print), Some(&frontmatter), &cli_args);
+        assert_eq!(style.text_size, 9);
+        assert_eq!(style.title, "Cli");
+    }
+
+    #[test]
+    fn project_keys_are_known() {
+        let file: ProjectConfigFile = toml::from_str(PROJECT).unwrap();
+        let unknown: Vec<_> = file.base.style.unknown.keys().collect();
+        assert!(unknown.is_empty(), "{:?}", unknown);
+        for profile in file.profile.values() {
+            assert!(profile.style.unknown.is_empty());
+        }
+
+        let file: ProjectConfigFile =
+            toml::from_str("font-mono = \"mono.ttf\"\ncolour = 1").unwrap();
+        let unknown: Vec<_> = file.base.style.unknown.keys().collect();
+        assert_eq!(unknown, ["colour"]);
+    }
+}
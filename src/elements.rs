@@ -0,0 +1,630 @@
+use genpdf::{
+    elements::{CellDecorator, Paragraph},
+    error::Error,
+    render,
+    style::{LineStyle, Style, StyledString},
+    Alignment, Context, Element, Margins, Mm, Position, RenderResult, Size,
+};
+
+use crate::{
+    base_style::TableRules,
+    glyph_fallback::GlyphFallback,
+    text_block::{TextBlock, TextRun},
+    tracking::RenderTracker,
+};
+
+/// Conversion factor from typographic points (used for font sizes) to millimeters
+const PT_TO_MM: f64 = 25.4 / 72.0;
+
+/// A list item that has a drawn checkbox instead of a bullet. This is used for GFM task list
+/// items (`- [ ]` and `- [x]`). The indentation matches the one of the normal `BulletPoint` so
+/// task items can be mixed with normal list items.
+pub struct CheckboxItem<E: Element> {
+    element: E,
+    checked: bool,
+    indent: Mm,
+    box_space: Mm,
+    box_rendered: bool,
+}
+
+impl<E: Element> CheckboxItem<E> {
+    pub fn new(element: E, checked: bool) -> Self {
+        Self {
+            element,
+            checked,
+            indent: Mm(10.0),
+            box_space: Mm(2.0),
+            box_rendered: false,
+        }
+    }
+
+    fn draw_box(&self, context: &Context, area: &render::Area<'_>, style: Style) {
+        let line_height = style.line_height(&context.font_cache).0;
+        let size = style.font_size() as f64 * PT_TO_MM * 0.65;
+
+        // Center the box on the first line of the element
+        let left = self.indent.0 - self.box_space.0 - size;
+        let top = (line_height - size) / 2.0;
+        let (right, bottom) = (left + size, top + size);
+
+        area.draw_line(
+            vec![
+                Position::new(left, top),
+                Position::new(right, top),
+                Position::new(right, bottom),
+                Position::new(left, bottom),
+                Position::new(left, top),
+            ],
+            LineStyle::new().with_thickness(0.2),
+        );
+
+        if self.checked {
+            area.draw_line(
+                vec![
+                    Position::new(left + size * 0.2, top + size * 0.5),
+                    Position::new(left + size * 0.42, top + size * 0.78),
+                    Position::new(left + size * 0.82, top + size * 0.2),
+                ],
+                LineStyle::new().with_thickness(0.35),
+            );
+        }
+    }
+}
+
+impl<E: Element> Element for CheckboxItem<E> {
+    fn render(
+        &mut self,
+        context: &Context,
+        area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        let mut element_area = area.clone();
+        element_area.add_offset(Position::new(self.indent, 0));
+        let mut result = self.element.render(context, element_area, style)?;
+        result.size.width += self.indent;
+
+        // The box is drawn next to the first line, which is not on this page if nothing of the
+        // element fits
+        if !self.box_rendered && result.size.height.0 > 0.0 {
+            self.draw_box(context, &area, style);
+            self.box_rendered = true;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Draws the rule lines of a table according to the configured [`TableRules`] and adds some
+/// padding around the cell contents
+pub struct RuleCellDecorator {
+    rules: TableRules,
+    header_rows: usize,
+    padding: Mm,
+    num_columns: usize,
+    num_rows: usize,
+}
+
+impl RuleCellDecorator {
+    pub fn new(rules: TableRules, header_rows: usize) -> Self {
+        Self {
+            rules,
+            header_rows,
+            padding: Mm(1.0),
+            num_columns: 0,
+            num_rows: 0,
+        }
+    }
+
+    /// Close the table at the bottom of a page if the table is continued on the next page
+    pub fn draw_page_end(&self, area: &render::Area<'_>) {
+        let thickness = match self.rules {
+            TableRules::None => return,
+            TableRules::Booktabs => 0.4,
+            TableRules::Grid => 0.2,
+        };
+        area.draw_line(
+            vec![Position::new(0, 0), Position::new(area.size().width, 0)],
+            LineStyle::new().with_thickness(thickness),
+        );
+    }
+}
+
+impl CellDecorator for RuleCellDecorator {
+    fn set_table_size(&mut self, num_columns: usize, num_rows: usize) {
+        self.num_columns = num_columns;
+        self.num_rows = num_rows;
+    }
+
+    fn prepare_cell<'p>(
+        &self,
+        _column: usize,
+        _row: usize,
+        mut area: render::Area<'p>,
+    ) -> render::Area<'p> {
+        area.add_margins(Margins::all(self.padding));
+        area
+    }
+
+    fn decorate_cell(
+        &mut self,
+        column: usize,
+        row: usize,
+        has_more: bool,
+        area: render::Area<'_>,
+        row_height: Mm,
+    ) -> Mm {
+        let height = row_height + self.padding + self.padding;
+        let width = area.size().width;
+
+        let hline = |y: Mm, thickness: f64| {
+            area.draw_line(
+                vec![Position::new(0, y), Position::new(width, y)],
+                LineStyle::new().with_thickness(thickness),
+            );
+        };
+        let vline = |x: Mm| {
+            area.draw_line(
+                vec![Position::new(x, 0), Position::new(x, height)],
+                LineStyle::new().with_thickness(0.2),
+            );
+        };
+
+        let is_last_row = row + 1 == self.num_rows && !has_more;
+
+        match self.rules {
+            TableRules::None => (),
+            TableRules::Booktabs => {
+                if row == 0 {
+                    hline(Mm(0.0), 0.4);
+                }
+                if row + 1 == self.header_rows && !is_last_row {
+                    hline(height, 0.2);
+                }
+                if is_last_row {
+                    hline(height, 0.4);
+                }
+            }
+            TableRules::Grid => {
+                hline(Mm(0.0), 0.2);
+                vline(Mm(0.0));
+                if column + 1 == self.num_columns {
+                    vline(width);
+                }
+                if is_last_row {
+                    hline(height, 0.2);
+                }
+            }
+        }
+
+        height
+    }
+}
+
+/// The content of a table cell. Cells are stored as the text runs they contain so that the
+/// paragraphs can be created again for the repeated header rows and so that the height of a row
+/// can be estimated before it is rendered.
+#[derive(Debug, Clone)]
+pub struct TableCell {
+    alignment: Alignment,
+    runs: Vec<TextRun>,
+}
+
+impl TableCell {
+    pub fn new(alignment: Alignment) -> Self {
+        Self {
+            alignment,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, run: TextRun) {
+        self.runs.push(run);
+    }
+}
+
+/// A table that is only split between rows when it doesn't fit on one page. The header rows are
+/// repeated at the top of every page the table continues on, optionally preceded by a
+/// "(continued)" label. Rows that are higher than a full page are still split.
+pub struct Table {
+    num_columns: usize,
+    header: Vec<Vec<TableCell>>,
+    body: Vec<Vec<TableCell>>,
+    decorator: RuleCellDecorator,
+    continued_label: Option<String>,
+    /// Index of the next body row that is rendered
+    next_row: usize,
+    /// A body row that didn't completely fit on the previous page
+    partial_row: Option<Vec<TextBlock>>,
+    /// Set if the table was moved to the next page without rendering anything
+    deferred: bool,
+    /// Header rows that don't leave space for the body on an empty page are not repeated
+    repeat_header: bool,
+    tracker: RenderTracker,
+}
+
+impl Table {
+    pub fn new(
+        num_columns: usize,
+        header: Vec<Vec<TableCell>>,
+        body: Vec<Vec<TableCell>>,
+        rules: TableRules,
+        tracker: &RenderTracker,
+    ) -> Self {
+        let mut decorator = RuleCellDecorator::new(rules, header.len());
+        decorator.set_table_size(num_columns, header.len() + body.len());
+
+        Self {
+            num_columns,
+            header,
+            body,
+            decorator,
+            continued_label: None,
+            next_row: 0,
+            partial_row: None,
+            deferred: false,
+            repeat_header: true,
+            tracker: tracker.clone(),
+        }
+    }
+
+    pub fn set_continued_label(&mut self, label: Option<String>) {
+        self.continued_label = label;
+    }
+
+    fn make_row(&self, row: &[TableCell]) -> Vec<TextBlock> {
+        row.iter()
+            .map(|cell| {
+                let mut p = TextBlock::new(&self.tracker);
+                p.set_alignment(cell.alignment);
+                for run in &cell.runs {
+                    p.push(run.clone());
+                }
+                p
+            })
+            .collect()
+    }
+
+    /// Estimate the height of a row by wrapping the words of every cell the same way a paragraph
+    /// does. Hyphenation is not taken into account, so this can overestimate the height.
+    fn estimate_row_height(
+        &self,
+        context: &Context,
+        area: &render::Area<'_>,
+        style: Style,
+        row: &[TableCell],
+    ) -> Mm {
+        let padding = self.decorator.padding.0 * 2.0;
+        let column_width = area.size().width.0 / self.num_columns.max(1) as f64 - padding;
+
+        let height = row
+            .iter()
+            .map(|cell| {
+                let text: Vec<StyledString> = cell.runs.iter().map(|it| it.text.clone()).collect();
+                estimate_text_height(context, style, &text, Mm(column_width)).0
+            })
+            .fold(0.0, f64::max);
+
+        Mm(height + padding)
+    }
+
+    fn render_row(
+        &mut self,
+        context: &Context,
+        area: &render::Area<'_>,
+        style: Style,
+        row: usize,
+        cells: &mut [TextBlock],
+    ) -> Result<(Mm, bool), Error> {
+        let areas = area.split_horizontally(&vec![1; cells.len()]);
+
+        let mut row_height = Mm(0.0);
+        let mut has_more = false;
+        for (column, (cell, cell_area)) in cells.iter_mut().zip(&areas).enumerate() {
+            let cell_area = self.decorator.prepare_cell(column, row, cell_area.clone());
+            let result = cell.render(context, cell_area, style)?;
+            has_more |= result.has_more;
+            if result.size.height > row_height {
+                row_height = result.size.height;
+            }
+        }
+
+        let mut height = row_height;
+        for (column, cell_area) in areas.into_iter().enumerate() {
+            let cell_height =
+                self.decorator.decorate_cell(column, row, has_more, cell_area, row_height);
+            if cell_height > height {
+                height = cell_height;
+            }
+        }
+
+        Ok((height, has_more))
+    }
+}
+
+impl Element for Table {
+    fn render(
+        &mut self,
+        context: &Context,
+        mut area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        let mut result = RenderResult::default();
+        let continued = self.next_row > 0 || self.partial_row.is_some();
+
+        // Move the table to the next page if not even the header and the first row fit on this
+        // one. This is only done once, so that rows higher than a page don't cause endless pages.
+        // On an empty page the table is rendered anyway and the rows are split, since genpdf
+        // fails if nothing is rendered on an empty page.
+        let page_start = self.tracker.is_page_start(area.size().height);
+        if !self.deferred && !continued && !page_start {
+            let mut required = Mm(0.0);
+            for row in self.header.iter().chain(self.body.first()) {
+                required += self.estimate_row_height(context, &area, style, row);
+            }
+            if required > area.size().height {
+                self.deferred = true;
+                result.has_more = true;
+                return Ok(result);
+            }
+        }
+        self.deferred = false;
+
+        if let (true, Some(label)) = (continued, &self.continued_label) {
+            let mut label_style = style;
+            label_style.set_italic();
+            let mut caption = Paragraph::default();
+            caption.set_alignment(Alignment::Right);
+            caption.push_styled(label.clone(), label_style);
+
+            let caption_result = caption.render(context, area.clone(), style)?;
+            area.add_offset(Position::new(0, caption_result.size.height));
+            result.size.height += caption_result.size.height;
+        }
+
+        if self.repeat_header || !continued {
+            for row in 0..self.header.len() {
+                let mut cells = self.make_row(&self.header[row]);
+                let (height, _) = self.render_row(context, &area, style, row, &mut cells)?;
+                area.add_offset(Position::new(0, height));
+                result.size.height += height;
+            }
+
+            // The header is clipped if it is higher than the page, and is not repeated if it
+            // leaves no space for the body, since the table would never end otherwise
+            if page_start && area.size().height < style.line_height(&context.font_cache) {
+                self.repeat_header = false;
+            }
+        }
+
+        let mut rendered_rows = 0;
+        loop {
+            let mut cells = match self.partial_row.take() {
+                Some(cells) => cells,
+                None if self.next_row < self.body.len() => {
+                    let row = &self.body[self.next_row];
+                    let estimate = self.estimate_row_height(context, &area, style, row);
+                    if rendered_rows > 0 && estimate > area.size().height {
+                        self.decorator.draw_page_end(&area);
+                        result.has_more = true;
+                        break;
+                    }
+                    let cells = self.make_row(row);
+                    self.next_row += 1;
+                    cells
+                }
+                None => break,
+            };
+
+            let row = self.header.len() + self.next_row - 1;
+            let (height, has_more) = self.render_row(context, &area, style, row, &mut cells)?;
+            area.add_offset(Position::new(0, height));
+            result.size.height += height;
+            rendered_rows += 1;
+
+            if has_more {
+                self.partial_row = Some(cells);
+                result.has_more = true;
+                break;
+            }
+        }
+
+        result.size.width = area.size().width;
+        Ok(result)
+    }
+}
+
+/// A line in the table of contents with the heading text, dot leaders and the right aligned page
+/// number. Entries are always a single line, text that is too long is not wrapped.
+pub struct TocEntry {
+    text: String,
+    page: String,
+    indent: Mm,
+    glyph_fallback: GlyphFallback,
+}
+
+impl TocEntry {
+    const LEADER: &'static str = ". ";
+
+    pub fn new(
+        text: impl Into<String>,
+        page: impl Into<String>,
+        indent: Mm,
+        glyph_fallback: &GlyphFallback,
+    ) -> Self {
+        Self {
+            text: text.into(),
+            page: page.into(),
+            indent,
+            glyph_fallback: glyph_fallback.clone(),
+        }
+    }
+}
+
+impl Element for TocEntry {
+    fn render(
+        &mut self,
+        context: &Context,
+        area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        let font_cache = &context.font_cache;
+        let line_height = style.line_height(font_cache);
+
+        let mut result = RenderResult::default();
+        if area.size().height < line_height {
+            result.has_more = true;
+            return Ok(result);
+        }
+
+        let width = area.size().width;
+        let page_width = style.str_width(font_cache, &self.page);
+        let position = Position::new(self.indent, 0);
+        self.glyph_fallback.print_str(&area, font_cache, position, style, &self.text)?;
+        area.print_str(font_cache, Position::new(width - page_width, 0), style, &self.page)?;
+
+        // The dots are placed on a common grid, so that they line up between the entries
+        let leader_width = style.str_width(font_cache, Self::LEADER).0;
+        let text_width = self.glyph_fallback.str_width(font_cache, &self.text, style);
+        let text_end = self.indent.0 + text_width.0;
+        let leader_start = (text_end / leader_width).ceil() * leader_width + leader_width;
+        let leader_end = (width - page_width).0 - leader_width;
+        let leaders = ((leader_end - leader_start) / leader_width).floor();
+        if leaders > 0.0 {
+            let leaders = Self::LEADER.repeat(leaders as usize);
+            area.print_str(font_cache, Position::new(leader_start, 0), style, leaders)?;
+        }
+
+        result.size = Size::new(width, line_height);
+        Ok(result)
+    }
+}
+
+/// A display equation with a right aligned equation number like "(2.3)". The number is centered
+/// vertically next to the equation, the equation is centered in the remaining width.
+pub struct NumberedEquation<E: Element> {
+    element: E,
+    number: String,
+}
+
+impl<E: Element> NumberedEquation<E> {
+    pub fn new(element: E, number: impl Into<String>) -> Self {
+        Self {
+            element,
+            number: number.into(),
+        }
+    }
+}
+
+impl<E: Element> Element for NumberedEquation<E> {
+    fn render(
+        &mut self,
+        context: &Context,
+        area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        let font_cache = &context.font_cache;
+        let number_width = style.str_width(font_cache, &self.number);
+
+        // Reserve the space of the number on both sides to keep the equation centered
+        let mut element_area = area.clone();
+        element_area.add_margins(Margins::trbl(0, number_width, 0, number_width));
+        let mut result = self.element.render(context, element_area, style)?;
+
+        if result.size.height > Mm(0.0) {
+            let line_height = style.line_height(font_cache);
+            let top = Mm((result.size.height.0 - line_height.0).max(0.0) / 2.0);
+            let left = area.size().width - number_width;
+            area.print_str(font_cache, Position::new(left, top), style, &self.number)?;
+            result.size.width = area.size().width;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Estimate the height of the given text when it is rendered as paragraph with the given width
+pub fn estimate_text_height(
+    context: &Context,
+    style: Style,
+    text: &[StyledString],
+    width: Mm,
+) -> Mm {
+    let mut lines = 1;
+    let mut line_width = 0.0;
+    let mut line_height = style.line_height(&context.font_cache).0;
+
+    for s in text {
+        let style = style.and(s.style);
+        line_height = line_height.max(style.line_height(&context.font_cache).0);
+        let space_width = style.str_width(&context.font_cache, " ").0;
+
+        for word in s.s.split_whitespace() {
+            let word_width = style.str_width(&context.font_cache, word).0;
+            if line_width > 0.0 && line_width + space_width + word_width > width.0 {
+                lines += 1;
+                line_width = word_width;
+            } else if line_width > 0.0 {
+                line_width += space_width + word_width;
+            } else {
+                line_width = word_width;
+            }
+        }
+    }
+
+    Mm(line_height * lines as f64)
+}
+
+/// Page break before a chapter, so that the chapter starts on a right-hand (odd) page. An empty
+/// page is inserted if necessary. Nothing happens if the chapter already starts at the top of a
+/// right-hand page.
+///
+/// The inserted pages are recorded in the tracker, so that they don't get a header and footer in
+/// the next render pass.
+pub struct ChapterBreak {
+    tracker: RenderTracker,
+    page_started: bool,
+    blank_page_inserted: bool,
+}
+
+impl ChapterBreak {
+    pub fn new(tracker: &RenderTracker) -> Self {
+        Self {
+            tracker: tracker.clone(),
+            page_started: false,
+            blank_page_inserted: false,
+        }
+    }
+}
+
+impl Element for ChapterBreak {
+    fn render(
+        &mut self,
+        _context: &Context,
+        area: render::Area<'_>,
+        _style: Style,
+    ) -> Result<RenderResult, Error> {
+        // genpdf starts a new page for a result with `has_more`, but fails with a page size error
+        // if the result is empty, since it assumes that nothing fits on a page. Like the genpdf
+        // PageBreak, a width is returned to avoid that, the height is zero so that the break
+        // doesn't take any space on the page.
+        let new_page = RenderResult {
+            size: Size::new(1, 0),
+            has_more: true,
+        };
+
+        if !self.page_started {
+            self.page_started = true;
+            if !self.tracker.is_page_start(area.size().height) {
+                return Ok(new_page);
+            }
+        }
+
+        if self.tracker.current_page() % 2 == 0 && !self.blank_page_inserted {
+            self.blank_page_inserted = true;
+            self.tracker.add_blank_page();
+            return Ok(new_page);
+        }
+
+        Ok(RenderResult::default())
+    }
+}
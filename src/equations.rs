@@ -0,0 +1,302 @@
+use std::collections::HashSet;
+
+use comrak::nodes::{AstNode, NodeValue};
+
+use crate::headings::Heading;
+
+/// A display equation of a math block, collected before the document is rendered so that
+/// references to equations can be resolved independent of their order in the document
+#[derive(Debug, Clone)]
+pub struct Equation {
+    /// The LaTeX source without the `\label{...}`
+    pub source: String,
+    /// Name from `\label{...}` that is used to reference the equation (`\eqref{name}`)
+    pub label: Option<String>,
+    /// Equation number like "3", or "2.3" if the headings are numbered. Only set for numbered
+    /// math blocks
+    pub number: Option<String>,
+}
+
+impl Equation {
+    /// The text that is printed for references and next to the equation, e.g. "(2.3)"
+    pub fn reference_text(&self) -> Option<String> {
+        self.number.as_ref().map(|number| format!("({})", number))
+    }
+
+    /// Name of the link anchor of the equation with the label. The labels have their own
+    /// namespace, so that they don't collide with the ids of the headings
+    pub fn anchor(label: &str) -> String {
+        format!("eq:{}", label)
+    }
+}
+
+/// Part of a text that can contain equation references
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextPart<'a> {
+    Text(&'a str),
+    /// `\eqref{label}`
+    Reference(&'a str),
+}
+
+/// Check if the code block info string marks a math block (`math` or `latex math`). Returns
+/// whether the equations of the block are numbered (`math numbered`).
+pub fn math_block_options(info: &str, number_all: bool) -> Option<bool> {
+    let words: Vec<&str> = info.split_whitespace().collect();
+    match words.as_slice() {
+        ["math", options @ ..] | ["latex", "math", options @ ..] => {
+            Some(number_all || options.contains(&"numbered"))
+        }
+        _ => None,
+    }
+}
+
+/// Split a math block into the single equations. Lines separated by a fully empty line are
+/// separate equations that are rendered vertically stacked
+pub fn split_equations(math: &str) -> Vec<String> {
+    let mut equations: Vec<String> = Vec::new();
+
+    let mut append_to_prev = false;
+    for line in math.lines() {
+        if line.trim().is_empty() {
+            append_to_prev = false;
+        } else {
+            if append_to_prev {
+                equations.last_mut().unwrap().push_str(line);
+            } else {
+                equations.push(line.to_string());
+            }
+            append_to_prev = true;
+        }
+    }
+
+    equations
+}
+
+/// Collect the equations of all math blocks in order. Equations of numbered blocks are numbered
+/// continuously, or per top level section if the headings are numbered.
+pub fn collect_equations<'a>(
+    root: &'a AstNode<'a>,
+    headings: &[Heading],
+    number_all: bool,
+) -> Vec<Equation> {
+    let mut equations = Vec::new();
+    let mut labels = HashSet::new();
+
+    let mut heading_index = 0;
+    let mut section: Option<String> = None;
+    let mut counter = 0;
+
+    for node in root.descendants() {
+        let (info, literal, line) = match &node.data.borrow().value {
+            NodeValue::Heading(_) => {
+                let heading = &headings[heading_index];
+                heading_index += 1;
+
+                let heading_section = heading
+                    .number
+                    .as_ref()
+                    .and_then(|number| number.split('.').next())
+                    .map(str::to_string);
+                if heading_section.is_some() && heading_section != section {
+                    section = heading_section;
+                    counter = 0;
+                }
+                continue;
+            }
+            NodeValue::CodeBlock(cb) => (
+                String::from_utf8_lossy(&cb.info).to_string(),
+                String::from_utf8_lossy(&cb.literal).to_string(),
+                node.data.borrow().start_line,
+            ),
+            _ => continue,
+        };
+
+        let numbered = match math_block_options(&info, number_all) {
+            Some(numbered) => numbered,
+            None => continue,
+        };
+
+        for math in split_equations(&literal) {
+            let (source, label) = strip_label(&math);
+
+            let number = match numbered {
+                true => {
+                    counter += 1;
+                    match &section {
+                        Some(section) => Some(format!("{}.{}", section, counter)),
+                        None => Some(counter.to_string()),
+                    }
+                }
+                false => None,
+            };
+
+            if let Some(label) = &label {
+                if !numbered {
+                    eprintln!(
+                        "Equation label '{}' in line {} can't be referenced, since the math block \
+                        is not numbered",
+                        label, line
+                    );
+                } else if !labels.insert(label.clone()) {
+                    eprintln!("Duplicate equation label '{}' in line {}", label, line);
+                } else if headings.iter().any(|heading| &heading.anchor == label) {
+                    eprintln!(
+                        "Equation label '{}' in line {} is also the id of a heading, links to \
+                        '#{}' lead to the heading, use \\eqref{{{}}} for the equation",
+                        label, line, label, label
+                    );
+                }
+            }
+
+            equations.push(Equation {
+                source,
+                label: label.filter(|_| numbered),
+                number,
+            });
+        }
+    }
+
+    equations
+}
+
+/// Remove the `\label{...}` from the equation, since it's not supported by the math renderer
+fn strip_label(math: &str) -> (String, Option<String>) {
+    let start = match math.find("\\label{") {
+        Some(start) => start,
+        None => return (math.to_string(), None),
+    };
+    let label_start = start + "\\label{".len();
+    let label_end = match math[label_start..].find('}') {
+        Some(end) => label_start + end,
+        None => return (math.to_string(), None),
+    };
+
+    let source = format!("{}{}", &math[..start], &math[label_end + 1..]);
+    let label = math[label_start..label_end].trim().to_string();
+    (source, Some(label))
+}
+
+/// Split the `\eqref{label}` references off the text
+pub fn split_references(text: &str) -> Vec<TextPart<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("\\eqref{") {
+        let label_start = start + "\\eqref{".len();
+        let label_end = match rest[label_start..].find('}') {
+            Some(end) => label_start + end,
+            None => break,
+        };
+
+        if start > 0 {
+            parts.push(TextPart::Text(&rest[..start]));
+        }
+        parts.push(TextPart::Reference(rest[label_start..label_end].trim()));
+        rest = &rest[label_end + 1..];
+    }
+
+    if !rest.is_empty() {
+        parts.push(TextPart::Text(rest));
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headings;
+
+    /// The numbers of the equations in the markdown, with numbered headings if `number_headings`
+    fn numbers(md: &str, number_headings: bool) -> Vec<Option<String>> {
+        let arena = comrak::Arena::new();
+        let root = comrak::parse_document(&arena, md, &comrak::ComrakOptions::default());
+        let mut headings = headings::collect_headings(root);
+        if number_headings {
+            headings::assign_numbers(&mut headings, 1);
+        }
+        collect_equations(root, &headings, false)
+            .into_iter()
+            .map(|eq| eq.reference_text())
+            .collect()
+    }
+
+    fn some(numbers: &[&str]) -> Vec<Option<String>> {
+        numbers.iter().map(|it| Some(it.to_string())).collect()
+    }
+
+    const SECTIONS: &str = "# One\n\n```math numbered\na\n\nb\n```\n\n\
+        # Two\n\n```math numbered\nc\n```\n\n## Sub\n\n```math numbered\nd\n\ne\n```\n\n\
+        # Three\n\n```math numbered\nf\n```\n";
+
+    #[test]
+    fn label_is_stripped() {
+        assert_eq!(
+            strip_label("E = mc^2 \\label{energy}"),
+            ("E = mc^2 ".to_string(), Some("energy".to_string()))
+        );
+        assert_eq!(
+            strip_label("\\label{ a }x"),
+            ("x".to_string(), Some("a".to_string()))
+        );
+        assert_eq!(strip_label("x^2"), ("x^2".to_string(), None));
+        assert_eq!(
+            strip_label("x \\label{a"),
+            ("x \\label{a".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn references_are_split() {
+        assert_eq!(
+            split_references("see \\eqref{a} and \\eqref{ b }."),
+            [
+                TextPart::Text("see "),
+                TextPart::Reference("a"),
+                TextPart::Text(" and "),
+                TextPart::Reference("b"),
+                TextPart::Text("."),
+            ]
+        );
+        assert_eq!(split_references("\\eqref{a}"), [TextPart::Reference("a")]);
+        assert_eq!(split_references("plain"), [TextPart::Text("plain")]);
+    }
+
+    #[test]
+    fn unterminated_reference_is_text() {
+        assert_eq!(
+            split_references("see \\eqref{a"),
+            [TextPart::Text("see \\eqref{a")]
+        );
+        assert_eq!(
+            split_references("\\eqref{a} and \\eqref{b"),
+            [TextPart::Reference("a"), TextPart::Text(" and \\eqref{b")]
+        );
+    }
+
+    #[test]
+    fn numbers_per_section() {
+        assert_eq!(
+            numbers(SECTIONS, true),
+            some(&["(1.1)", "(1.2)", "(2.1)", "(2.2)", "(2.3)", "(3.1)"])
+        );
+    }
+
+    #[test]
+    fn numbers_without_sections() {
+        assert_eq!(
+            numbers(SECTIONS, false),
+            some(&["(1)", "(2)", "(3)", "(4)", "(5)", "(6)"])
+        );
+    }
+
+    #[test]
+    fn unnumbered_blocks_have_no_number() {
+        let md = "```math numbered\na\n```\n\n```math\nb \\label{b}\n```\n\n\
+            ```math numbered\nc\n```\n";
+        assert_eq!(
+            numbers(md, false),
+            vec![Some("(1)".to_string()), None, Some("(2)".to_string())]
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use std::path::Path;
+
+use clap::Args;
+use genpdf::fonts::FontData;
+use serde::Deserialize;
+
+use crate::resources;
+
+/// Font files (TTF or OTF) that replace the embedded fonts. The font styles that are not set fall
+/// back to the regular font file, so that a custom font isn't mixed with the embedded font. For
+/// the same reason the other styles can only be set together with the regular font file, see
+/// [`FontFiles::check`]. If no font file is set at all, the embedded fonts are used.
+#[derive(Debug, Clone, Default, Args, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FontFiles {
+    /// Font file for the regular text. Default is the embedded TeX Gyre Pagella
+    #[arg(long)]
+    pub font_regular: Option<String>,
+
+    /// Font file for bold text
+    #[arg(long)]
+    pub font_bold: Option<String>,
+
+    /// Font file for italic text
+    #[arg(long)]
+    pub font_italic: Option<String>,
+
+    /// Font file for bold italic text
+    #[arg(long)]
+    pub font_bold_italic: Option<String>,
+
+    /// Font file for code. Default is the embedded Inconsolata
+    #[arg(long)]
+    pub font_mono: Option<String>,
+
+    /// OpenType math font file for the math blocks. The font must contain a MATH table. Default
+    /// is the embedded TeX Gyre Pagella Math
+    #[arg(long)]
+    pub font_math: Option<String>,
+
+    /// Font file that is used for characters that the text font has no glyph for. Can be given
+    /// multiple times, the fallback fonts are tried in the given order
+    #[arg(long)]
+    #[serde(default)]
+    pub font_fallback: Vec<String>,
+}
+
+impl FontFiles {
+    /// Use the font files of `other` for the fonts that are not set
+    pub fn or(self, other: FontFiles) -> Self {
+        Self {
+            font_regular: self.font_regular.or(other.font_regular),
+            font_bold: self.font_bold.or(other.font_bold),
+            font_italic: self.font_italic.or(other.font_italic),
+            font_bold_italic: self.font_bold_italic.or(other.font_bold_italic),
+            font_mono: self.font_mono.or(other.font_mono),
+            font_math: self.font_math.or(other.font_math),
+            font_fallback: match self.font_fallback.is_empty() {
+                true => other.font_fallback,
+                false => self.font_fallback,
+            },
+        }
+    }
+
+    /// Check that the styles of the text font are only set together with the regular font file,
+    /// otherwise they would be mixed with the embedded regular font
+    pub fn check(&self) -> Result<(), String> {
+        if self.font_regular.is_some() {
+            return Ok(());
+        }
+
+        let styles = [
+            ("font-bold", &self.font_bold),
+            ("font-italic", &self.font_italic),
+            ("font-bold-italic", &self.font_bold_italic),
+        ];
+        match styles.iter().find(|(_, file)| file.is_some()) {
+            Some((name, _)) => Err(format!(
+                "'{}' requires 'font-regular', so that the font isn't mixed with the embedded font",
+                name
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolve relative paths from the given directory
+    pub fn relative_to(self, dir: &Path) -> Self {
+        let resolve = |path: Option<String>| {
+            path.map(|path| dir.join(path).to_string_lossy().to_string())
+        };
+
+        Self {
+            font_regular: resolve(self.font_regular),
+            font_bold: resolve(self.font_bold),
+            font_italic: resolve(self.font_italic),
+            font_bold_italic: resolve(self.font_bold_italic),
+            font_mono: resolve(self.font_mono),
+            font_math: resolve(self.font_math),
+            font_fallback: self
+                .font_fallback
+                .into_iter()
+                .filter_map(|path| resolve(Some(path)))
+                .collect(),
+        }
+    }
+}
+
+/// Read the font file, or decompress the embedded font if no file is given. Returns the raw font
+/// data, which is needed in addition to the parsed font for the math font and for the glyph
+/// coverage.
+pub fn read_font(path: Option<&str>, embedded: &str) -> Result<Vec<u8>, String> {
+    match path {
+        Some(path) => read_font_file(path),
+        None => Ok(resources::get_decompress(embedded)),
+    }
+}
+
+/// Read the font file, for fonts that have no embedded default
+pub fn read_font_file(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Can't read font file '{}': {}", path, e))
+}
+
+/// Load the font file, or the embedded font if no file is given
+pub fn load_font(path: Option<&str>, embedded: &str) -> Result<FontData, String> {
+    parse_font(read_font(path, embedded)?, path)
+}
+
+/// Check that the math font file has the OpenType MATH table, which the math layout is based on
+pub fn check_math_table(data: &[u8], path: &str) -> Result<(), String> {
+    let face = ttf_parser::Face::parse(data, 0)
+        .map_err(|e| format!("Can't load math font file '{}': {}", path, e))?;
+    match face.tables().math {
+        Some(_) => Ok(()),
+        None => Err(format!(
+            "The math font file '{}' has no MATH table, only OpenType math fonts like \
+            'Latin Modern Math' or 'STIX Two Math' can be used",
+            path
+        )),
+    }
+}
+
+/// Parse the font data that was read from the file, or from the embedded font if the path is not
+/// set
+pub fn parse_font(data: Vec<u8>, path: Option<&str>) -> Result<FontData, String> {
+    FontData::new(data, None).map_err(|e| match path {
+        Some(path) => format!("Can't load font file '{}': {}", path, e),
+        None => format!("Can't load embedded font: {}", e),
+    })
+}
@@ -0,0 +1,243 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    rc::Rc,
+};
+
+use genpdf::{
+    elements::Paragraph,
+    error::Error,
+    render,
+    fonts::{Font, FontFamily},
+    style::{LineStyle, Style, StyledString},
+    Context, Element, Mm, PageDecorator, Position,
+};
+
+use crate::{elements::estimate_text_height, numbering::to_superscript, tracking::LayoutInfo};
+
+#[derive(Debug)]
+struct FootnoteState {
+    /// Names of the footnote definitions in the order comrak numbered them
+    definitions: Vec<String>,
+    /// Number of every referenced footnote definition
+    numbers: HashMap<String, usize>,
+    /// Paragraphs of every footnote, indexed by number - 1
+    texts: Vec<Vec<Vec<StyledString>>>,
+    /// Layout of the previous render pass, used to find the page of every footnote reference
+    layout: Option<LayoutInfo>,
+    /// Font for the superscript markers, the text fonts only contain superscript 1 to 3
+    marker_font: FontFamily<Font>,
+    /// Number of footnote paragraphs that didn't fit on the last decorated page
+    carried_over: usize,
+}
+
+/// The footnotes of the document. They are numbered continuously in the order they are
+/// referenced. The footnote text is printed at the bottom of the page with the reference, which
+/// is only known after rendering, so the page is taken from the layout of the previous pass.
+///
+/// This is shared between the document building and the page decorator that prints the notes.
+#[derive(Debug, Clone)]
+pub struct Footnotes(Rc<RefCell<FootnoteState>>);
+
+impl Footnotes {
+    pub fn new(
+        definitions: Vec<String>,
+        layout: Option<LayoutInfo>,
+        marker_font: FontFamily<Font>,
+    ) -> Self {
+        Self(Rc::new(RefCell::new(FootnoteState {
+            definitions,
+            numbers: HashMap::new(),
+            texts: Vec::new(),
+            layout,
+            marker_font,
+            carried_over: 0,
+        })))
+    }
+
+    /// Anchor name that is used to track the element with the footnote reference. The colon
+    /// can't be part of a heading slug, so the anchors don't collide with heading anchors
+    pub fn anchor(number: usize) -> String {
+        format!("fn:{}", number)
+    }
+
+    /// The superscript footnote number in the given style
+    pub fn marker(&self, number: usize, mut style: Style) -> StyledString {
+        style.set_font_family(self.0.borrow().marker_font);
+        StyledString::new(to_superscript(&number.to_string()), style)
+    }
+
+    /// Get the number for a reference to a footnote definition. comrak replaces the reference
+    /// names with the 1-based index of the definition, which is also done for numeric names, so
+    /// the key is always looked up by index.
+    pub fn reference(&self, key: &str) -> usize {
+        let mut state = self.0.borrow_mut();
+
+        let name = key
+            .parse::<usize>()
+            .ok()
+            .and_then(|ix| state.definitions.get(ix.wrapping_sub(1)))
+            .cloned()
+            .unwrap_or_else(|| key.to_string());
+
+        if let Some(number) = state.numbers.get(&name) {
+            return *number;
+        }
+
+        state.texts.push(Vec::new());
+        let number = state.texts.len();
+        state.numbers.insert(name, number);
+        number
+    }
+
+    /// Add a footnote that is not defined in the document (e.g. for the URL of a link) and get
+    /// its number
+    pub fn add_note(&self, text: Vec<StyledString>) -> usize {
+        let mut state = self.0.borrow_mut();
+        state.texts.push(vec![text]);
+        state.texts.len()
+    }
+
+    /// Set the paragraphs of a footnote definition. Definitions that are never referenced are
+    /// ignored
+    pub fn set_text(&self, name: &str, paragraphs: Vec<Vec<StyledString>>) {
+        let mut state = self.0.borrow_mut();
+        if let Some(number) = state.numbers.get(name).copied() {
+            state.texts[number - 1] = paragraphs;
+        }
+    }
+
+    /// The number of footnote paragraphs that didn't fit on the last page and are missing
+    pub fn carried_over(&self) -> usize {
+        self.0.borrow().carried_over
+    }
+
+    /// The footnotes that are referenced on the given page with their numbers
+    pub fn on_page(&self, page: usize) -> Vec<(usize, Vec<Vec<StyledString>>)> {
+        let state = self.0.borrow();
+        let layout = match &state.layout {
+            Some(layout) => layout,
+            None => return Vec::new(),
+        };
+
+        state
+            .texts
+            .iter()
+            .enumerate()
+            .map(|(i, text)| (i + 1, text))
+            .filter(|(number, _)| layout.anchor_pages.get(&Self::anchor(*number)) == Some(&page))
+            .map(|(number, text)| (number, text.clone()))
+            .collect()
+    }
+}
+
+/// Page decorator that prints the footnotes that are referenced on a page at the bottom of the
+/// page, below a short rule. The space of the footnotes is removed from the content area.
+///
+/// The footnotes take at most half of the page. Footnotes that don't fit are continued at the
+/// bottom of the next page.
+pub struct FootnoteDecorator<D: PageDecorator> {
+    inner: D,
+    footnotes: Footnotes,
+    font_size: u8,
+    page: usize,
+    /// Paragraphs of the footnotes that are printed next, with their estimated height. The first
+    /// paragraph can be partially rendered on the previous page.
+    pending: VecDeque<(Paragraph, Mm)>,
+}
+
+impl<D: PageDecorator> FootnoteDecorator<D> {
+    /// Space above and below the rule that separates the footnotes from the content
+    const RULE_SPACE: f64 = 2.0;
+    /// The maximum share of the page that is used for footnotes
+    const MAX_SHARE: f64 = 0.5;
+
+    pub fn new(inner: D, footnotes: Footnotes, font_size: u8) -> Self {
+        Self {
+            inner,
+            footnotes,
+            font_size,
+            page: 0,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<D: PageDecorator> PageDecorator for FootnoteDecorator<D> {
+    fn decorate_page<'a>(
+        &mut self,
+        context: &Context,
+        area: render::Area<'a>,
+        style: Style,
+    ) -> Result<render::Area<'a>, Error> {
+        let mut area = self.inner.decorate_page(context, area, style)?;
+        self.page += 1;
+
+        let mut note_style = style;
+        note_style.set_font_size(self.font_size);
+        let width = area.size().width;
+
+        for (number, paragraphs) in self.footnotes.on_page(self.page) {
+            for (i, mut text) in paragraphs.into_iter().enumerate() {
+                if i == 0 {
+                    text.insert(0, StyledString::new(" ", Style::new()));
+                    text.insert(0, self.footnotes.marker(number, Style::new()));
+                }
+                let height = estimate_text_height(context, note_style, &text, width);
+
+                let mut p = Paragraph::default();
+                for s in text {
+                    p.push(s);
+                }
+                self.pending.push_back((p, height));
+            }
+        }
+        self.footnotes.0.borrow_mut().carried_over = self.pending.len();
+        if self.pending.is_empty() {
+            return Ok(area);
+        }
+
+        let available = area.size().height.0 - Self::RULE_SPACE * 2.0;
+        let notes_height: f64 = self.pending.iter().map(|(_, height)| height.0).sum();
+        let notes_height = notes_height.min(available * Self::MAX_SHARE);
+        let content_height = available - notes_height;
+
+        let mut notes_area = area.clone();
+        notes_area.add_offset(Position::new(0, content_height + Self::RULE_SPACE));
+        notes_area.draw_line(
+            vec![Position::new(0, 0), Position::new(width.0 / 3.0, 0)],
+            LineStyle::new().with_thickness(0.2),
+        );
+        notes_area.add_offset(Position::new(0, Self::RULE_SPACE));
+        notes_area.set_height(Mm(notes_height));
+
+        // The estimated height can be off, so the paragraphs are rendered until the space is
+        // used up and the rest is continued on the next page
+        while let Some((mut p, height)) = self.pending.pop_front() {
+            let result = p.render(context, notes_area.clone(), note_style)?;
+            notes_area.add_offset(Position::new(0, result.size.height));
+            if result.has_more {
+                self.pending.push_front((p, height));
+                break;
+            }
+        }
+        self.footnotes.0.borrow_mut().carried_over = self.pending.len();
+
+        area.set_height(Mm(content_height));
+        Ok(area)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headings::slugify;
+
+    #[test]
+    fn anchor_differs_from_heading_slug() {
+        assert_eq!(Footnotes::anchor(1), "fn:1");
+        assert_ne!(slugify("Footnote 1"), Footnotes::anchor(1));
+        assert_ne!(slugify("fn:1"), Footnotes::anchor(1));
+        assert_ne!(slugify("fn 1"), Footnotes::anchor(1));
+    }
+}
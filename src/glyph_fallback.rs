@@ -0,0 +1,216 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashSet},
+    rc::Rc,
+};
+
+use genpdf::{
+    error::Error,
+    fonts::{Font, FontCache, FontData, FontFamily},
+    render,
+    style::Style,
+    Mm, Position,
+};
+
+use crate::font_files;
+
+/// The characters a font has glyphs for, taken from the unicode subtables of the cmap table
+#[derive(Debug, Clone)]
+pub struct GlyphCoverage(Rc<HashSet<char>>);
+
+impl GlyphCoverage {
+    pub fn new(data: &[u8]) -> Result<Self, String> {
+        let face = ttf_parser::Face::parse(data, 0)
+            .map_err(|e| format!("Can't read the glyph coverage of the font: {}", e))?;
+
+        let mut chars = HashSet::new();
+        if let Some(cmap) = face.tables().cmap {
+            for subtable in cmap.subtables.into_iter().filter(|it| it.is_unicode()) {
+                subtable.codepoints(|codepoint| {
+                    if let Some(c) = char::from_u32(codepoint) {
+                        chars.insert(c);
+                    }
+                });
+            }
+        }
+
+        Ok(Self(Rc::new(chars)))
+    }
+
+    /// Check if the font has a glyph for the character. Whitespace and control characters are
+    /// always considered as covered, since they are handled by the layout
+    pub fn contains(&self, c: char) -> bool {
+        c.is_whitespace() || c.is_control() || self.0.contains(&c)
+    }
+}
+
+/// The glyph coverage of every style of the text font, since the styles can be loaded from
+/// different font files
+#[derive(Debug, Clone)]
+pub struct TextCoverage {
+    pub regular: GlyphCoverage,
+    pub bold: GlyphCoverage,
+    pub italic: GlyphCoverage,
+    pub bold_italic: GlyphCoverage,
+}
+
+impl TextCoverage {
+    fn for_style(&self, style: Style) -> &GlyphCoverage {
+        match (style.is_bold(), style.is_italic()) {
+            (false, false) => &self.regular,
+            (true, false) => &self.bold,
+            (false, true) => &self.italic,
+            (true, true) => &self.bold_italic,
+        }
+    }
+}
+
+/// A font that is used for the characters that the text font doesn't cover. The same font file
+/// is used for all styles.
+pub struct FallbackFont {
+    pub family: FontFamily<FontData>,
+    pub coverage: GlyphCoverage,
+}
+
+impl FallbackFont {
+    pub fn load(path: &str, allow_subsetting: bool) -> Result<Self, String> {
+        let data = font_files::read_font_file(path)?;
+        let coverage = GlyphCoverage::new(&data)
+            .map_err(|e| format!("Error in font file '{}': {}", path, e))?;
+        let font = font_files::parse_font(data, Some(path))?;
+
+        let family = FontFamily {
+            regular: font.clone(),
+            bold: font.clone(),
+            italic: font.clone(),
+            bold_italic: font,
+        };
+
+        Ok(Self {
+            family: family.with_subsetting(allow_subsetting),
+            coverage,
+        })
+    }
+}
+
+/// Splits text into runs that are printed with the text font or with one of the fallback fonts.
+/// The fallback fonts are tried in order for every character that the text font doesn't cover.
+///
+/// The characters that no font covers are collected, so that they can be reported after the
+/// document was rendered. Clones share the collected characters.
+#[derive(Clone)]
+pub struct GlyphFallback {
+    text: TextCoverage,
+    monospace: GlyphCoverage,
+    fallbacks: Vec<(GlyphCoverage, FontFamily<Font>)>,
+    missing: Rc<RefCell<BTreeSet<char>>>,
+}
+
+impl GlyphFallback {
+    /// The fallback families are the ones that were added to the document for the fallback fonts,
+    /// in the same order
+    pub fn new(
+        text: &TextCoverage,
+        monospace: &GlyphCoverage,
+        fallbacks: &[FallbackFont],
+        families: Vec<FontFamily<Font>>,
+    ) -> Self {
+        Self {
+            text: text.clone(),
+            monospace: monospace.clone(),
+            fallbacks: fallbacks
+                .iter()
+                .map(|font| font.coverage.clone())
+                .zip(families)
+                .collect(),
+            missing: Rc::new(RefCell::new(BTreeSet::new())),
+        }
+    }
+
+    /// The index of the fallback font for the character, `None` if the font of the text should
+    /// be used. That font is also used if no font covers the character.
+    fn fallback_for(&self, primary: &GlyphCoverage, c: char) -> Option<usize> {
+        if primary.contains(c) {
+            return None;
+        }
+
+        let fallback = self
+            .fallbacks
+            .iter()
+            .position(|(coverage, _)| coverage.contains(c));
+        if fallback.is_none() {
+            self.missing.borrow_mut().insert(c);
+        }
+        fallback
+    }
+
+    /// Split the text that is printed with the text font in the given style into runs with the
+    /// same font. The runs that use a fallback font get its font family in their style.
+    pub fn split<'t>(&self, text: &'t str, style: Style) -> Vec<(&'t str, Style)> {
+        self.split_with(self.text.for_style(style), text, style)
+    }
+
+    /// Like [`GlyphFallback::split`], for text that is printed with the monospace font
+    pub fn split_monospace<'t>(&self, text: &'t str, style: Style) -> Vec<(&'t str, Style)> {
+        self.split_with(&self.monospace, text, style)
+    }
+
+    fn split_with<'t>(
+        &self,
+        primary: &GlyphCoverage,
+        text: &'t str,
+        style: Style,
+    ) -> Vec<(&'t str, Style)> {
+        let style_for = |fallback: Option<usize>| match fallback {
+            Some(index) => style.with_font_family(self.fallbacks[index].1),
+            None => style,
+        };
+
+        let mut runs = Vec::new();
+        let mut start = 0;
+        let mut current = None;
+        for (i, c) in text.char_indices() {
+            let fallback = self.fallback_for(primary, c);
+            if i > start && fallback != current {
+                runs.push((&text[start..i], style_for(current)));
+                start = i;
+            }
+            current = fallback;
+        }
+        if start < text.len() {
+            runs.push((&text[start..], style_for(current)));
+        }
+
+        runs
+    }
+
+    /// The width of the text with the fallback fonts
+    pub fn str_width(&self, font_cache: &FontCache, text: &str, style: Style) -> Mm {
+        self.split(text, style)
+            .into_iter()
+            .map(|(run, style)| style.str_width(font_cache, run))
+            .fold(Mm(0.0), |width, run_width| width + run_width)
+    }
+
+    /// Print the text with the fallback fonts at the position, like [`render::Area::print_str`]
+    pub fn print_str(
+        &self,
+        area: &render::Area<'_>,
+        font_cache: &FontCache,
+        position: Position,
+        style: Style,
+        text: &str,
+    ) -> Result<(), Error> {
+        let mut x = position.x;
+        for (run, style) in self.split(text, style) {
+            area.print_str(font_cache, Position::new(x, position.y), style, run)?;
+            x += style.str_width(font_cache, run);
+        }
+        Ok(())
+    }
+
+    /// The characters that were printed so far, but that no font has glyphs for
+    pub fn missing(&self) -> BTreeSet<char> {
+        self.missing.borrow().clone()
+    }
+}
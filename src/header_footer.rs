@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use genpdf::{
+    error::Error, render, style::Style, Context, Margins, Mm, PageDecorator, Position,
+};
+
+use crate::{
+    base_style::DocumentStyle,
+    glyph_fallback::GlyphFallback,
+    headings::Heading,
+    title::TitleLayout,
+    tracking::{LayoutInfo, RenderTracker},
+};
+
+/// Headings up to this level are used for the `{section}` placeholder
+const SECTION_LEVEL: u8 = 2;
+/// Distance between the header and the content, and between the content and the footer
+const HEADER_SPACE: f64 = 8.0;
+
+/// Templates for the left, center and right part of the header or footer. The templates can
+/// contain the placeholders `{page}`, `{pages}`, `{title}` and `{section}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderFooter {
+    pub left: String,
+    pub center: String,
+    pub right: String,
+}
+
+impl HeaderFooter {
+    fn is_empty(&self) -> bool {
+        self.left.is_empty() && self.center.is_empty() && self.right.is_empty()
+    }
+
+    /// Check if the templates contain placeholders that depend on the final layout
+    pub fn uses_layout(&self) -> bool {
+        [&self.left, &self.center, &self.right]
+            .iter()
+            .any(|it| it.contains("{pages}") || it.contains("{section}"))
+    }
+}
+
+/// Page decorator that applies the page margins and prints the running header and footer into
+/// the top and bottom margin. The page count and the sections are taken from the layout of the
+/// previous render pass.
+///
+/// In the two-sided layout the left margin is the inner margin, so the margins and the left and
+/// right part of the header and footer are swapped on even (left-hand) pages.
+///
+/// Inside of landscape sections the area of portrait pages is turned into a landscape area, see
+/// [`crate::landscape::OrientationBreak`].
+pub struct HeaderFooterDecorator {
+    margins: Margins,
+    header: HeaderFooter,
+    footer: HeaderFooter,
+    title: String,
+    page_count: Option<usize>,
+    /// Start page and text of the headings that are used for `{section}`
+    sections: Vec<(usize, String)>,
+    /// Orientation of the pages that start with an orientation break, from the previous pass
+    orientation_pages: HashMap<usize, bool>,
+    /// Empty pages inserted before chapters, they don't get a header and footer
+    blank_pages: Vec<usize>,
+    /// Don't print the header and footer on the first page, since it's the title page
+    skip_first_page: bool,
+    two_sided: bool,
+    tracker: RenderTracker,
+    glyph_fallback: GlyphFallback,
+    page: usize,
+}
+
+impl HeaderFooterDecorator {
+    pub fn new(
+        docstyle: &DocumentStyle,
+        headings: &[Heading],
+        layout: Option<&LayoutInfo>,
+        tracker: &RenderTracker,
+        glyph_fallback: &GlyphFallback,
+    ) -> Self {
+        let sections = match layout {
+            Some(layout) => headings
+                .iter()
+                .filter(|heading| heading.level <= SECTION_LEVEL)
+                .filter_map(|heading| {
+                    let page = layout.anchor_pages.get(&heading.anchor)?;
+                    Some((*page, heading.numbered_text()))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let has_title = docstyle.title_block_layout() != TitleLayout::None;
+
+        Self {
+            margins: docstyle.page_margins,
+            header: docstyle.header.clone(),
+            footer: docstyle.footer.clone(),
+            title: docstyle.title.clone(),
+            page_count: layout.map(|layout| layout.page_count),
+            sections,
+            orientation_pages: layout
+                .map(|layout| layout.orientation_pages.clone())
+                .unwrap_or_default(),
+            blank_pages: layout
+                .map(|layout| layout.blank_pages.clone())
+                .unwrap_or_default(),
+            skip_first_page: has_title && !docstyle.title_page_header,
+            two_sided: docstyle.two_sided,
+            tracker: tracker.clone(),
+            glyph_fallback: glyph_fallback.clone(),
+            page: 0,
+        }
+    }
+
+    /// Check if the current page is a left-hand page whose layout is mirrored
+    fn is_mirrored(&self) -> bool {
+        self.two_sided && self.page % 2 == 0
+    }
+
+    fn margins(&self) -> Margins {
+        match self.is_mirrored() {
+            true => Margins {
+                left: self.margins.right,
+                right: self.margins.left,
+                ..self.margins
+            },
+            false => self.margins,
+        }
+    }
+
+    /// The first section that starts on the page, otherwise the last section before the page
+    fn section(&self, page: usize) -> &str {
+        self.sections
+            .iter()
+            .find(|(start, _)| *start == page)
+            .or_else(|| self.sections.iter().rev().find(|(start, _)| *start < page))
+            .map(|(_, text)| text.as_str())
+            .unwrap_or("")
+    }
+
+    fn expand(&self, template: &str) -> String {
+        let pages = match self.page_count {
+            Some(pages) => pages.to_string(),
+            None => "?".to_string(),
+        };
+
+        template
+            .replace("{page}", &self.page.to_string())
+            .replace("{pages}", &pages)
+            .replace("{title}", &self.title)
+            .replace("{section}", self.section(self.page))
+    }
+
+    /// Print the left, center and right part at the given height, between the page margins
+    fn print_line(
+        &self,
+        context: &Context,
+        area: &render::Area<'_>,
+        style: Style,
+        line: &HeaderFooter,
+        top: Mm,
+    ) -> Result<(), Error> {
+        let font_cache = &context.font_cache;
+        let fallback = &self.glyph_fallback;
+        let margins = self.margins();
+        let left = margins.left;
+        let right = area.size().width - margins.right;
+        let (left_template, right_template) = match self.is_mirrored() {
+            true => (&line.right, &line.left),
+            false => (&line.left, &line.right),
+        };
+
+        let text = self.expand(left_template);
+        fallback.print_str(area, font_cache, Position::new(left, top), style, &text)?;
+
+        let text = self.expand(&line.center);
+        let width = fallback.str_width(font_cache, &text, style);
+        let center = Mm((left.0 + right.0 - width.0) / 2.0);
+        fallback.print_str(area, font_cache, Position::new(center, top), style, &text)?;
+
+        let text = self.expand(right_template);
+        let width = fallback.str_width(font_cache, &text, style);
+        let position = Position::new(right - width, top);
+        fallback.print_str(area, font_cache, position, style, &text)?;
+
+        Ok(())
+    }
+}
+
+impl PageDecorator for HeaderFooterDecorator {
+    fn decorate_page<'a>(
+        &mut self,
+        context: &Context,
+        mut area: render::Area<'a>,
+        style: Style,
+    ) -> Result<render::Area<'a>, Error> {
+        self.page += 1;
+        if let Some(landscape) = self.orientation_pages.get(&self.page) {
+            self.tracker.set_landscape(*landscape);
+        }
+
+        // The content is laid out as if the page was rotated, with the top left corner at the
+        // same position. The page itself is rotated while postprocessing.
+        let page_size = area.size();
+        if self.tracker.is_landscape() && page_size.width < page_size.height {
+            area.set_width(page_size.height);
+            area.set_height(page_size.width);
+            self.tracker.add_rotated_page(self.page);
+        }
+
+        let skip =
+            (self.skip_first_page && self.page == 1) || self.blank_pages.contains(&self.page);
+        if !skip {
+            let line_height = style.line_height(&context.font_cache);
+
+            if !self.header.is_empty() {
+                let top = Mm((self.margins.top.0 - HEADER_SPACE - line_height.0).max(0.0));
+                self.print_line(context, &area, style, &self.header, top)?;
+            }
+            if !self.footer.is_empty() {
+                let top = area.size().height - self.margins.bottom + Mm(HEADER_SPACE);
+                self.print_line(context, &area, style, &self.footer, top)?;
+            }
+        }
+
+        let margins = self.margins();
+        area.add_margins(margins);
+        self.tracker.set_page_margins(margins);
+        Ok(area)
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+
+use comrak::nodes::{AstNode, NodeValue};
+
+use crate::{attributes::Attributes, node_text};
+
+/// Information about a heading that is collected before the document is rendered, so that
+/// references to headings can be resolved independent of their order in the document
+#[derive(Debug, Clone)]
+pub struct Heading {
+    pub level: u8,
+    /// The plain text of the heading, without the attributes
+    pub text: String,
+    /// Unique id that is used to reference the heading in links (`[see intro](#introduction)`)
+    pub anchor: String,
+    /// Excluded from the section numbering with the `{.unnumbered}` attribute
+    pub unnumbered: bool,
+    /// Hierarchical section number like "2.3.1", see [`assign_numbers`]
+    pub number: Option<String>,
+    /// Language of the section from the `{lang=de}` attribute
+    pub lang: Option<String>,
+}
+
+impl Heading {
+    /// The heading text prefixed with the section number, if the heading has one
+    pub fn numbered_text(&self) -> String {
+        match &self.number {
+            Some(number) => format!("{} {}", number, self.text),
+            None => self.text.clone(),
+        }
+    }
+}
+
+/// Collect all headings of the document in order. Trailing attribute blocks (`{#id .class}`) are
+/// removed from the heading text in the AST. Headings without an explicit id get a slug of the
+/// heading text as anchor, duplicates are made unique by appending a number.
+pub fn collect_headings<'a>(root: &'a AstNode<'a>) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut anchors = HashSet::new();
+
+    for node in root.descendants() {
+        let (level, line) = match &node.data.borrow().value {
+            NodeValue::Heading(h) => (h.level, node.data.borrow().start_line),
+            _ => continue,
+        };
+
+        let attributes = strip_attributes(node).unwrap_or_default();
+        for class in attributes.classes.iter().filter(|it| *it != "unnumbered") {
+            eprintln!("Unsupported heading class '.{}' in line {}", class, line);
+        }
+        for (key, _) in attributes.values.iter().filter(|(key, _)| key != "lang") {
+            eprintln!("Unsupported heading attribute '{}' in line {}", key, line);
+        }
+
+        let text = node_text(node).trim().to_string();
+
+        let anchor = match &attributes.id {
+            Some(id) => {
+                if anchors.contains(id) {
+                    eprintln!("Duplicate heading id '{}' in line {}", id, line);
+                }
+                id.clone()
+            }
+            None => {
+                let slug = slugify(&text);
+                let mut anchor = slug.clone();
+                let mut n = 1;
+                while anchors.contains(&anchor) {
+                    anchor = format!("{}-{}", slug, n);
+                    n += 1;
+                }
+                anchor
+            }
+        };
+        anchors.insert(anchor.clone());
+
+        headings.push(Heading {
+            level,
+            text,
+            anchor,
+            unnumbered: attributes.classes.iter().any(|it| it == "unnumbered"),
+            number: None,
+            lang: attributes
+                .values
+                .iter()
+                .find(|(key, _)| key == "lang")
+                .map(|(_, lang)| lang.clone()),
+        });
+    }
+
+    headings
+}
+
+/// Number the headings hierarchically like LaTeX sections ("2.3.1"). Headings above the start
+/// level and unnumbered headings don't get a number, unnumbered headings also don't increase the
+/// counters.
+pub fn assign_numbers(headings: &mut [Heading], start_level: u8) {
+    let start_level = start_level.clamp(1, 6) as usize;
+    let mut counters = [0usize; 6];
+
+    for heading in headings.iter_mut() {
+        let level = heading.level.clamp(1, 6) as usize;
+        if level < start_level || heading.unnumbered {
+            continue;
+        }
+
+        counters[level - 1] += 1;
+        counters[level..].iter_mut().for_each(|it| *it = 0);
+
+        let number = counters[start_level - 1..level]
+            .iter()
+            .map(|it| it.to_string())
+            .collect::<Vec<_>>()
+            .join(".");
+        heading.number = Some(number);
+    }
+}
+
+/// Remove a trailing attribute block from the last text node of the heading
+fn strip_attributes<'a>(heading: &'a AstNode<'a>) -> Option<Attributes> {
+    let last = heading.last_child()?;
+
+    let text = match &last.data.borrow().value {
+        NodeValue::Text(t) => String::from_utf8_lossy(t).to_string(),
+        _ => return None,
+    };
+    let (remaining, attributes) = Attributes::split_trailing(&text)?;
+
+    last.data.borrow_mut().value = NodeValue::Text(remaining.as_bytes().to_vec());
+    Some(attributes)
+}
+
+/// Create an anchor id from the heading text similar to GitHub: lowercase alphanumeric words
+/// separated by dashes
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            c if c.is_whitespace() => Some('-'),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugify_words() {
+        assert_eq!(slugify("Introduction"), "introduction");
+        assert_eq!(slugify("Related Work"), "related-work");
+        assert_eq!(slugify("What's new?"), "whats-new");
+        assert_eq!(slugify("snake_case and-dash"), "snake_case-and-dash");
+    }
+
+    #[test]
+    fn slugify_unicode() {
+        assert_eq!(slugify("Über Größen"), "über-größen");
+        assert_eq!(slugify("1. Setup"), "1-setup");
+    }
+
+    /// Headings with the level and the unnumbered flag, numbered from the start level
+    fn numbers(headings: &[(u8, bool)], start_level: u8) -> Vec<Option<String>> {
+        let mut headings: Vec<Heading> = headings
+            .iter()
+            .map(|(level, unnumbered)| Heading {
+                level: *level,
+                text: String::new(),
+                anchor: String::new(),
+                unnumbered: *unnumbered,
+                number: None,
+                lang: None,
+            })
+            .collect();
+        assign_numbers(&mut headings, start_level);
+        headings.into_iter().map(|it| it.number).collect()
+    }
+
+    fn some(numbers: &[&str]) -> Vec<Option<String>> {
+        numbers.iter().map(|it| Some(it.to_string())).collect()
+    }
+
+    #[test]
+    fn numbers_reset_below_level() {
+        let headings = [(1, false), (2, false), (2, false), (1, false), (2, false)];
+        assert_eq!(numbers(&headings, 1), some(&["1", "1.1", "1.2", "2", "2.1"]));
+    }
+
+    #[test]
+    fn numbers_skipped_level() {
+        assert_eq!(numbers(&[(1, false), (3, false)], 1), some(&["1", "1.0.1"]));
+    }
+
+    #[test]
+    fn numbers_from_start_level() {
+        let headings = [(1, false), (2, false), (3, false), (2, false)];
+        let mut expected = vec![None];
+        expected.extend(some(&["1", "1.1", "2"]));
+        assert_eq!(numbers(&headings, 2), expected);
+    }
+
+    #[test]
+    fn unnumbered_headings_keep_counters() {
+        let headings = [(1, false), (1, true), (2, false), (1, false)];
+        assert_eq!(
+            numbers(&headings, 1),
+            vec![Some("1".to_string()), None, Some("1.1".to_string()), Some("2".to_string())]
+        );
+    }
+
+    #[test]
+    fn unnumbered_attribute() {
+        let arena = comrak::Arena::new();
+        let md = "# Preface {.unnumbered}\n\n# Intro\n\n## Scope\n";
+        let root = comrak::parse_document(&arena, md, &comrak::ComrakOptions::default());
+
+        let mut headings = collect_headings(root);
+        assign_numbers(&mut headings, 1);
+        let numbered: Vec<_> = headings.iter().map(Heading::numbered_text).collect();
+        assert_eq!(numbered, ["Preface", "1 Intro", "1.1 Scope"]);
+    }
+}
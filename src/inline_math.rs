@@ -0,0 +1,930 @@
+use std::{iter::Peekable, str::Chars};
+
+use genpdf::{
+    fonts::{Font, FontFamily},
+    style::{Style, StyledString},
+};
+
+use crate::{config, numbering::superscript_char};
+
+/// Marks inline code spans that were created from inline math by [`extract_inline_math`]
+const PLACEHOLDER_MARKER: char = '\u{E000}';
+
+/// Thin space, used after commas and for `\,`
+const THIN_SPACE: &str = "\u{2009}";
+/// Medium space, used around binary operators and relations
+const MEDIUM_SPACE: &str = "\u{2005}";
+
+/// An inline math span (`$x^2$`) of the markdown source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlineMath {
+    /// The LaTeX source between the dollar signs
+    pub source: String,
+    /// Position of the opening dollar sign in the markdown source, starting at 1
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A part of a converted formula. genpdf can't shift the baseline inside of a paragraph, so
+/// scripts are kept separate and printed with unicode super- and subscript characters if
+/// possible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MathRun {
+    Text(String),
+    Superscript(String),
+    Subscript(String),
+}
+
+impl InlineMath {
+    /// Parse the content of a code span that was created by [`extract_inline_math`]
+    pub fn from_placeholder(code: &str) -> Option<Self> {
+        let code = code.strip_prefix(PLACEHOLDER_MARKER)?;
+        let (position, source) = code.split_once(PLACEHOLDER_MARKER)?;
+        let (line, column) = position.split_once(':')?;
+
+        Some(Self {
+            source: source.to_string(),
+            line: line.parse().ok()?,
+            column: column.parse().ok()?,
+        })
+    }
+
+    /// Inline code span that stores the math, so that the LaTeX source is not touched by the
+    /// markdown parser (backslash escapes, emphasis, ...)
+    fn placeholder(&self) -> String {
+        let content = format!(
+            "{marker}{}:{}{marker}{}",
+            self.line,
+            self.column,
+            self.source,
+            marker = PLACEHOLDER_MARKER
+        );
+
+        // The fence must be longer than any backtick run in the content
+        let mut longest_run = 0;
+        let mut run = 0;
+        for c in content.chars() {
+            run = if c == '`' { run + 1 } else { 0 };
+            longest_run = longest_run.max(run);
+        }
+        let fence = "`".repeat(longest_run + 1);
+
+        // One space on both sides is removed again by the markdown parser
+        format!("{fence} {content} {fence}")
+    }
+
+    /// Approximate the LaTeX source with unicode math text.
+    ///
+    /// This is not typeset by the math renderer of the math blocks, since genpdf paragraphs can
+    /// only contain text and the math renderer creates separate block elements. So fractions are
+    /// written as `¹⁄₂` or `a/b`, roots as `√(x)` and scripts without unicode variant (like in
+    /// `e^{i\pi}`) are printed smaller on the baseline instead of raised. Accents and font
+    /// commands (`\hat{x}`, `\mathbf{x}`) can't be represented and are replaced by their
+    /// argument.
+    pub fn convert(&self) -> Result<Vec<MathRun>, String> {
+        let mut parser = Parser {
+            chars: self.source.chars().peekable(),
+        };
+        let runs = parser.parse_sequence(false)?;
+        Ok(merge_runs(runs))
+    }
+
+    /// The converted formula as plain text (e.g. for the PDF outline), or the source if it can't
+    /// be converted
+    pub fn plain_text(&self) -> String {
+        match self.convert() {
+            Ok(runs) => runs
+                .iter()
+                .map(|run| match run {
+                    MathRun::Text(t) => t.clone(),
+                    MathRun::Superscript(t) => unicode_superscript(t).unwrap_or_else(|| t.clone()),
+                    MathRun::Subscript(t) => unicode_subscript(t).unwrap_or_else(|| t.clone()),
+                })
+                .collect(),
+            Err(_) => self.source.clone(),
+        }
+    }
+}
+
+/// Create the text runs of a converted formula. The math font doesn't contain unicode script
+/// characters, so they are taken from the script font. Scripts without unicode variant are
+/// printed in a smaller size on the baseline.
+pub fn styled_runs(
+    runs: Vec<MathRun>,
+    style: Style,
+    math_font: FontFamily<Font>,
+    script_font: FontFamily<Font>,
+) -> Vec<StyledString> {
+    let math_style = style.with_font_family(math_font);
+    let script_style = style.with_font_family(script_font);
+    let small_style = math_style.with_font_size((style.font_size() as f64 * 0.7).round() as u8);
+
+    runs.into_iter()
+        .map(|run| match run {
+            MathRun::Text(t) => StyledString::new(t, math_style),
+            MathRun::Superscript(t) => match unicode_superscript(&t) {
+                Some(t) => StyledString::new(t, script_style),
+                None => StyledString::new(t, small_style),
+            },
+            MathRun::Subscript(t) => match unicode_subscript(&t) {
+                Some(t) => StyledString::new(t, script_style),
+                None => StyledString::new(t, small_style),
+            },
+        })
+        .collect()
+}
+
+/// Replace the inline math spans (`$...$`) in the markdown source with placeholder code spans.
+///
+/// The opening dollar sign must be followed by a non-space character, the closing one must
+/// follow a non-space character and must not be followed by a digit, so that prices like
+/// "$5 and $10" are kept. Math can't span multiple lines. Escaped dollar signs (`\$`), code spans
+/// (also over multiple lines), fenced and indented code blocks and the frontmatter are left
+/// untouched.
+pub fn extract_inline_math(md: &str) -> String {
+    let lines: Vec<&str> = md.split_inclusive('\n').collect();
+    let mut result = String::with_capacity(md.len());
+
+    let front_matter = front_matter_len(&lines);
+    lines[..front_matter].iter().for_each(|line| result.push_str(line));
+
+    // Fence character and length of the open fenced code block
+    let mut fence: Option<(char, usize)> = None;
+    // First line of the paragraph that is collected, code spans can continue over its lines
+    let mut block_start: Option<usize> = None;
+    // Indented lines in list items are continued paragraphs and not code blocks
+    let mut in_list = false;
+    let mut previous_blank = true;
+
+    for (line_index, line) in lines.iter().enumerate().skip(front_matter) {
+        let trimmed = line.trim_start();
+        let indent = indent_width(line);
+        let blank = trimmed.trim_end().is_empty();
+
+        let fence_char = trimmed.chars().next().filter(|c| *c == '`' || *c == '~');
+        let fence_len = fence_char
+            .map(|c| trimmed.chars().take_while(|it| *it == c).count())
+            .unwrap_or(0);
+        let is_fence = indent < 4 && fence_len >= 3;
+        let is_indented_code = fence.is_none()
+            && block_start.is_none()
+            && !blank
+            && indent >= 4
+            && !in_list;
+        let is_heading = indent < 4 && trimmed.starts_with('#');
+
+        if fence.is_some() || is_fence || is_indented_code || is_heading || blank {
+            if let Some(start) = block_start.take() {
+                replace_in_block(&lines[start..line_index].concat(), start + 1, &mut result);
+            }
+        }
+
+        if let (Some(c), true) = (fence_char, is_fence) {
+            match fence {
+                None => fence = Some((c, fence_len)),
+                Some((open_char, open_len)) if open_char == c && fence_len >= open_len => {
+                    fence = None
+                }
+                Some(_) => (),
+            }
+            result.push_str(line);
+        } else if fence.is_some() || is_indented_code || blank {
+            result.push_str(line);
+        } else if is_heading {
+            replace_in_block(line, line_index + 1, &mut result);
+        } else {
+            if indent < 4 && is_list_item(trimmed) {
+                in_list = true;
+            } else if indent == 0 && previous_blank {
+                in_list = false;
+            }
+            block_start.get_or_insert(line_index);
+        }
+
+        previous_blank = blank;
+    }
+
+    if let Some(start) = block_start {
+        replace_in_block(&lines[start..].concat(), start + 1, &mut result);
+    }
+
+    result
+}
+
+/// The number of lines of the frontmatter at the start of the document, 0 if there is none.
+/// Blocks that are no frontmatter are markdown, see [`config::is_frontmatter`]
+fn front_matter_len(lines: &[&str]) -> usize {
+    let is_delimiter = |line: &&str| line.trim_end() == "---";
+    if !lines.first().map(is_delimiter).unwrap_or(false) {
+        return 0;
+    }
+
+    let len = match lines.iter().skip(1).position(is_delimiter) {
+        Some(end) => end + 2,
+        None => return 0,
+    };
+    match config::is_frontmatter(&lines[..len].concat()) {
+        true => len,
+        false => 0,
+    }
+}
+
+/// Width of the indentation of the line, tabs count as 4 spaces
+fn indent_width(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+/// Check if the line starts a bullet or ordered list item
+fn is_list_item(trimmed: &str) -> bool {
+    let rest = match trimmed.strip_prefix(['-', '*', '+']) {
+        Some(rest) => rest,
+        None => {
+            let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+            match digits {
+                0 => return false,
+                _ => match trimmed[digits..].strip_prefix(['.', ')']) {
+                    Some(rest) => rest,
+                    None => return false,
+                },
+            }
+        }
+    };
+
+    rest.is_empty() || rest.starts_with([' ', '\t', '\r', '\n'])
+}
+
+/// Replace the inline math in the lines of a paragraph, starting with the given line number
+fn replace_in_block(block: &str, first_line: usize, result: &mut String) {
+    let chars: Vec<char> = block.chars().collect();
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(chars.iter().enumerate().filter(|(_, c)| **c == '\n').map(|(i, _)| i + 1))
+        .collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                result.push(chars[i]);
+                result.push(chars[i + 1]);
+                i += 2;
+            }
+            '`' => {
+                // Copy the code span including the closing backticks
+                let run = chars[i..].iter().take_while(|c| **c == '`').count();
+                let end = find_backtick_run(&chars, i + run, run).unwrap_or(i + run);
+                result.extend(&chars[i..end]);
+                i = end;
+            }
+            '$' => match find_math_end(&chars, i) {
+                Some(end) => {
+                    let line = line_starts.partition_point(|start| *start <= i) - 1;
+                    let math = InlineMath {
+                        source: chars[i + 1..end].iter().collect(),
+                        line: first_line + line,
+                        column: i - line_starts[line] + 1,
+                    };
+                    result.push_str(&math.placeholder());
+                    i = end + 1;
+                }
+                None => {
+                    // `$$` is not inline math, so both are skipped
+                    let run = chars[i..].iter().take_while(|c| **c == '$').count();
+                    result.extend(&chars[i..i + run]);
+                    i += run;
+                }
+            },
+            c => {
+                result.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Find the end of a closing backtick run with exactly the given length
+fn find_backtick_run(chars: &[char], start: usize, len: usize) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        if chars[i] == '`' {
+            let run = chars[i..].iter().take_while(|c| **c == '`').count();
+            if run == len {
+                return Some(i + run);
+            }
+            i += run;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Find the index of the closing dollar sign for the dollar sign at `start`
+fn find_math_end(chars: &[char], start: usize) -> Option<usize> {
+    let first = *chars.get(start + 1)?;
+    if first == '$' || first.is_whitespace() {
+        return None;
+    }
+
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\n' => return None,
+            '\\' => i += 2,
+            '$' => {
+                let closes = !chars[i - 1].is_whitespace()
+                    && !chars.get(i + 1).map(char::is_ascii_digit).unwrap_or(false);
+                if closes {
+                    return Some(i);
+                }
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Recursive descent parser for the supported subset of LaTeX math
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    /// Parse atoms until the end of the input, or until the closing brace if `in_group` is set
+    fn parse_sequence(&mut self, in_group: bool) -> Result<Vec<MathRun>, String> {
+        let mut runs = Vec::new();
+
+        loop {
+            match self.chars.peek() {
+                None if in_group => return Err("missing closing '}'".to_string()),
+                None => return Ok(runs),
+                Some('}') if in_group => {
+                    self.chars.next();
+                    return Ok(runs);
+                }
+                Some('}') => return Err("unexpected '}'".to_string()),
+                Some('^') | Some('_') => {
+                    let c = self.chars.next().unwrap();
+                    let argument = self
+                        .parse_atom()?
+                        .ok_or_else(|| format!("missing argument for '{}'", c))?;
+                    let text = flatten(&argument);
+                    runs.push(match c {
+                        '^' => MathRun::Superscript(text),
+                        _ => MathRun::Subscript(text),
+                    });
+                }
+                Some(_) => {
+                    if let Some(atom) = self.parse_atom()? {
+                        runs.extend(atom);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Parse a single character, command or group. Returns `None` at the end of the input
+    fn parse_atom(&mut self) -> Result<Option<Vec<MathRun>>, String> {
+        self.skip_whitespace();
+
+        let c = match self.chars.next() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let text = match c {
+            '{' => return self.parse_sequence(true).map(Some),
+            '}' => return Err("unexpected '}'".to_string()),
+            '\\' => return self.parse_command().map(Some),
+            '&' => return Err("alignment with '&' is not supported in inline math".to_string()),
+            '^' | '_' => return Err(format!("double script '{}'", c)),
+            'h' => "ℎ".to_string(),
+            'a'..='z' => math_italic(c, 'a', 0x1D44E),
+            'A'..='Z' => math_italic(c, 'A', 0x1D434),
+            '-' => format!("{MEDIUM_SPACE}−{MEDIUM_SPACE}"),
+            '+' | '=' | '<' | '>' => format!("{MEDIUM_SPACE}{c}{MEDIUM_SPACE}"),
+            ',' => format!(",{THIN_SPACE}"),
+            '\'' => "′".to_string(),
+            c => c.to_string(),
+        };
+
+        Ok(Some(vec![MathRun::Text(text)]))
+    }
+
+    fn parse_command(&mut self) -> Result<Vec<MathRun>, String> {
+        let mut name = String::new();
+        while let Some(c) = self.chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+            name.push(*c);
+            self.chars.next();
+        }
+        if name.is_empty() {
+            // Single character commands like `\,` or `\{`
+            name.push(self.chars.next().ok_or("missing command name after '\\'")?);
+        }
+
+        let text = match name.as_str() {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.parse_argument(&name)?;
+                let denominator = self.parse_argument(&name)?;
+                return Ok(fraction(numerator, denominator));
+            }
+            "sqrt" => {
+                self.skip_whitespace();
+                let index = match self.chars.peek() == Some(&'[') {
+                    true => Some(self.parse_root_index()?),
+                    false => None,
+                };
+                let argument = self.parse_argument(&name)?;
+                let mut runs = match index.as_deref() {
+                    None | Some("2") => vec![MathRun::Text("√".to_string())],
+                    Some("3") => vec![MathRun::Text("∛".to_string())],
+                    Some("4") => vec![MathRun::Text("∜".to_string())],
+                    Some(index) => vec![
+                        MathRun::Superscript(index.to_string()),
+                        MathRun::Text("√".to_string()),
+                    ],
+                };
+                runs.extend(parenthesize(argument));
+                return Ok(runs);
+            }
+            // Accents and fonts can't be shown with unicode text, so only the argument is kept
+            "hat" | "widehat" | "bar" | "overline" | "underline" | "vec" | "overrightarrow"
+            | "tilde" | "widetilde" | "dot" | "ddot" | "check" | "breve" | "acute" | "grave"
+            | "mathbf" | "mathit" | "mathcal" | "mathbb" | "mathsf" | "mathtt" | "mathfrak"
+            | "boldsymbol" | "bm" => return self.parse_argument(&name),
+            "text" | "mathrm" | "operatorname" => self.parse_raw_argument(&name)?,
+            "left" | "right" => {
+                // Delimiters can't be scaled, so only the delimiter itself is printed
+                self.skip_whitespace();
+                if self.chars.peek() == Some(&'.') {
+                    self.chars.next();
+                }
+                String::new()
+            }
+            "sin" | "cos" | "tan" | "cot" | "arcsin" | "arccos" | "arctan" | "sinh" | "cosh"
+            | "tanh" | "log" | "ln" | "exp" | "lim" | "max" | "min" | "sup" | "inf" | "det"
+            | "dim" | "ker" | "gcd" => format!("{name}{THIN_SPACE}"),
+            "," => THIN_SPACE.to_string(),
+            ":" | ";" | " " => MEDIUM_SPACE.to_string(),
+            "quad" => "\u{2003}".to_string(),
+            "qquad" => "\u{2003}\u{2003}".to_string(),
+            "!" => String::new(),
+            "{" | "}" | "$" | "%" | "#" | "&" | "_" => name.clone(),
+            "\\" => return Err("line breaks are not supported in inline math".to_string()),
+            _ => match symbol(&name) {
+                Some((symbol, true)) => format!("{MEDIUM_SPACE}{symbol}{MEDIUM_SPACE}"),
+                Some((symbol, false)) => symbol.to_string(),
+                // Other commands with an argument are replaced by the argument
+                None if self.next_is_group() => return self.parse_argument(&name),
+                None => return Err(format!("unknown command '\\{}'", name)),
+            },
+        };
+
+        Ok(vec![MathRun::Text(text)])
+    }
+
+    /// Parse the argument of a command
+    fn parse_argument(&mut self, command: &str) -> Result<Vec<MathRun>, String> {
+        self.parse_atom()?
+            .ok_or_else(|| format!("missing argument for '\\{}'", command))
+    }
+
+    /// Parse the index of a root (`\sqrt[3]{x}`), starting at the opening bracket
+    fn parse_root_index(&mut self) -> Result<String, String> {
+        self.chars.next();
+        let mut source = String::new();
+        for c in self.chars.by_ref() {
+            if c == ']' {
+                let mut parser = Parser {
+                    chars: source.chars().peekable(),
+                };
+                return Ok(flatten(&parser.parse_sequence(false)?));
+            }
+            source.push(c);
+        }
+
+        Err("missing closing ']'".to_string())
+    }
+
+    /// Whether the next character after whitespace opens a group
+    fn next_is_group(&mut self) -> bool {
+        self.skip_whitespace();
+        self.chars.peek() == Some(&'{')
+    }
+
+    /// Read the braced argument of a command as upright text without parsing it
+    fn parse_raw_argument(&mut self, command: &str) -> Result<String, String> {
+        self.skip_whitespace();
+        if self.chars.next() != Some('{') {
+            return Err(format!("missing '{{' after '\\{}'", command));
+        }
+
+        let mut text = String::new();
+        let mut depth = 0;
+        for c in self.chars.by_ref() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Ok(text),
+                '}' => depth -= 1,
+                _ => (),
+            }
+            text.push(c);
+        }
+
+        Err("missing closing '}'".to_string())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            self.chars.next();
+        }
+    }
+}
+
+fn math_italic(c: char, base: char, italic_base: u32) -> String {
+    let offset = c as u32 - base as u32;
+    char::from_u32(italic_base + offset)
+        .unwrap_or(c)
+        .to_string()
+}
+
+/// Symbol for the command name. The flag is set for binary operators and relations, which are
+/// surrounded by spaces
+fn symbol(name: &str) -> Option<(&'static str, bool)> {
+    let symbol = match name {
+        "alpha" => ("𝛼", false),
+        "beta" => ("𝛽", false),
+        "gamma" => ("𝛾", false),
+        "delta" => ("𝛿", false),
+        "epsilon" => ("𝜖", false),
+        "varepsilon" => ("𝜀", false),
+        "zeta" => ("𝜁", false),
+        "eta" => ("𝜂", false),
+        "theta" => ("𝜃", false),
+        "vartheta" => ("𝜗", false),
+        "iota" => ("𝜄", false),
+        "kappa" => ("𝜅", false),
+        "lambda" => ("𝜆", false),
+        "mu" => ("𝜇", false),
+        "nu" => ("𝜈", false),
+        "xi" => ("𝜉", false),
+        "pi" => ("𝜋", false),
+        "varpi" => ("𝜛", false),
+        "rho" => ("𝜌", false),
+        "varrho" => ("𝜚", false),
+        "sigma" => ("𝜎", false),
+        "varsigma" => ("𝜍", false),
+        "tau" => ("𝜏", false),
+        "upsilon" => ("𝜐", false),
+        "phi" => ("𝜙", false),
+        "varphi" => ("𝜑", false),
+        "chi" => ("𝜒", false),
+        "psi" => ("𝜓", false),
+        "omega" => ("𝜔", false),
+        "Gamma" => ("Γ", false),
+        "Delta" => ("Δ", false),
+        "Theta" => ("Θ", false),
+        "Lambda" => ("Λ", false),
+        "Xi" => ("Ξ", false),
+        "Pi" => ("Π", false),
+        "Sigma" => ("Σ", false),
+        "Upsilon" => ("Υ", false),
+        "Phi" => ("Φ", false),
+        "Psi" => ("Ψ", false),
+        "Omega" => ("Ω", false),
+
+        "cdot" => ("⋅", true),
+        "times" => ("×", true),
+        "div" => ("÷", true),
+        "pm" => ("±", true),
+        "mp" => ("∓", true),
+        "circ" => ("∘", true),
+        "oplus" => ("⊕", true),
+        "otimes" => ("⊗", true),
+        "cup" => ("∪", true),
+        "cap" => ("∩", true),
+        "wedge" | "land" => ("∧", true),
+        "vee" | "lor" => ("∨", true),
+
+        "leq" | "le" => ("≤", true),
+        "geq" | "ge" => ("≥", true),
+        "neq" | "ne" => ("≠", true),
+        "ll" => ("≪", true),
+        "gg" => ("≫", true),
+        "approx" => ("≈", true),
+        "equiv" => ("≡", true),
+        "sim" => ("∼", true),
+        "simeq" => ("≃", true),
+        "propto" => ("∝", true),
+        "in" => ("∈", true),
+        "notin" => ("∉", true),
+        "ni" => ("∋", true),
+        "subset" => ("⊂", true),
+        "supset" => ("⊃", true),
+        "subseteq" => ("⊆", true),
+        "supseteq" => ("⊇", true),
+        "perp" => ("⊥", true),
+        "parallel" => ("∥", true),
+        "to" | "rightarrow" => ("→", true),
+        "leftarrow" | "gets" => ("←", true),
+        "leftrightarrow" => ("↔", true),
+        "Rightarrow" | "implies" => ("⇒", true),
+        "Leftarrow" => ("⇐", true),
+        "Leftrightarrow" | "iff" => ("⇔", true),
+        "mapsto" => ("↦", true),
+
+        "infty" => ("∞", false),
+        "partial" => ("∂", false),
+        "nabla" => ("∇", false),
+        "sum" => ("∑", false),
+        "prod" => ("∏", false),
+        "int" => ("∫", false),
+        "iint" => ("∬", false),
+        "oint" => ("∮", false),
+        "forall" => ("∀", false),
+        "exists" => ("∃", false),
+        "nexists" => ("∄", false),
+        "emptyset" | "varnothing" => ("∅", false),
+        "neg" | "lnot" => ("¬", false),
+        "angle" => ("∠", false),
+        "ell" => ("ℓ", false),
+        "hbar" => ("ℏ", false),
+        "ldots" | "dots" => ("…", false),
+        "cdots" => ("⋯", false),
+        "vdots" => ("⋮", false),
+        "ddots" => ("⋱", false),
+        "prime" => ("′", false),
+        "langle" => ("⟨", false),
+        "rangle" => ("⟩", false),
+        "lfloor" => ("⌊", false),
+        "rfloor" => ("⌋", false),
+        "lceil" => ("⌈", false),
+        "rceil" => ("⌉", false),
+        "|" | "Vert" => ("‖", false),
+        "vert" => ("|", false),
+        "degree" => ("°", false),
+        "R" => ("ℝ", false),
+        "N" => ("ℕ", false),
+        "Z" => ("ℤ", false),
+        "Q" => ("ℚ", false),
+        "C" => ("ℂ", false),
+        _ => return None,
+    };
+
+    Some(symbol)
+}
+
+/// The text of all runs without spacing. Scripts of scripts are not supported and just appended
+fn flatten(runs: &[MathRun]) -> String {
+    runs.iter()
+        .flat_map(|run| match run {
+            MathRun::Text(t) | MathRun::Superscript(t) | MathRun::Subscript(t) => t.chars(),
+        })
+        .filter(|c| !c.is_whitespace())
+        .collect()
+}
+
+/// A fraction of simple numbers (like `\frac{1}{2}`) is written with unicode scripts as `¹⁄₂`,
+/// other fractions as `a/b`. Numerator and denominator are put in parentheses if they contain an
+/// operator.
+fn fraction(numerator: Vec<MathRun>, denominator: Vec<MathRun>) -> Vec<MathRun> {
+    let is_text = |runs: &[MathRun]| runs.iter().all(|run| matches!(run, MathRun::Text(_)));
+    if is_text(&numerator) && is_text(&denominator) {
+        let (numerator, denominator) = (flatten(&numerator), flatten(&denominator));
+        let scripts =
+            unicode_superscript(&numerator).is_some() && unicode_subscript(&denominator).is_some();
+        if !numerator.is_empty() && !denominator.is_empty() && scripts {
+            return vec![
+                MathRun::Superscript(numerator),
+                MathRun::Text("⁄".to_string()),
+                MathRun::Subscript(denominator),
+            ];
+        }
+    }
+
+    // Operators are surrounded by spaces, see `Parser::parse_atom`
+    let group = |runs: Vec<MathRun>| {
+        let text: String = runs
+            .iter()
+            .map(|run| match run {
+                MathRun::Text(t) | MathRun::Superscript(t) | MathRun::Subscript(t) => t.as_str(),
+            })
+            .collect();
+        match text.trim().contains(char::is_whitespace) {
+            true => parenthesize(runs),
+            false => runs,
+        }
+    };
+
+    let mut runs = group(numerator);
+    runs.push(MathRun::Text("/".to_string()));
+    runs.extend(group(denominator));
+    runs
+}
+
+/// Put parentheses around the runs if they consist of more than one character
+fn parenthesize(runs: Vec<MathRun>) -> Vec<MathRun> {
+    let single = matches!(runs.as_slice(), [MathRun::Text(t)] if t.trim().chars().count() == 1);
+    if single {
+        return runs;
+    }
+
+    let mut result = vec![MathRun::Text("(".to_string())];
+    result.extend(runs);
+    result.push(MathRun::Text(")".to_string()));
+    result
+}
+
+/// Merge consecutive text runs and remove the spacing at the start and the end
+fn merge_runs(runs: Vec<MathRun>) -> Vec<MathRun> {
+    let mut merged: Vec<MathRun> = Vec::new();
+    for run in runs {
+        match (merged.last_mut(), run) {
+            (Some(MathRun::Text(last)), MathRun::Text(t)) => last.push_str(&t),
+            (_, run) => merged.push(run),
+        }
+    }
+
+    let is_space = |c: char| c.is_whitespace();
+    if let Some(MathRun::Text(t)) = merged.first_mut() {
+        *t = t.trim_start_matches(is_space).to_string();
+    }
+    if let Some(MathRun::Text(t)) = merged.last_mut() {
+        *t = t.trim_end_matches(is_space).to_string();
+    }
+    merged.retain(|run| !matches!(run, MathRun::Text(t) if t.is_empty()));
+
+    merged
+}
+
+/// Unicode superscript variant of the script text, if every character has one
+fn unicode_superscript(text: &str) -> Option<String> {
+    text.chars()
+        .map(|c| match c {
+            '𝑛' => Some('ⁿ'),
+            c => superscript_char(c),
+        })
+        .collect()
+}
+
+/// Unicode subscript variant of the script text, if every character has one
+fn unicode_subscript(text: &str) -> Option<String> {
+    text.chars()
+        .map(|c| match c {
+            '0' => Some('₀'),
+            '1' => Some('₁'),
+            '2' => Some('₂'),
+            '3' => Some('₃'),
+            '4' => Some('₄'),
+            '5' => Some('₅'),
+            '6' => Some('₆'),
+            '7' => Some('₇'),
+            '8' => Some('₈'),
+            '9' => Some('₉'),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn math(source: &str, line: usize, column: usize) -> String {
+        InlineMath {
+            source: source.to_string(),
+            line,
+            column,
+        }
+        .placeholder()
+    }
+
+    #[test]
+    fn extracts_math() {
+        assert_eq!(extract_inline_math("a $x^2$ b"), format!("a {} b", math("x^2", 1, 3)));
+        assert_eq!(
+            extract_inline_math("a\nb $x$ $y$\n"),
+            format!("a\nb {} {}\n", math("x", 2, 3), math("y", 2, 7))
+        );
+    }
+
+    #[test]
+    fn placeholder_round_trip() {
+        let placeholder = math("a `b` c", 3, 4);
+        let code = placeholder.trim_matches('`').trim();
+        assert_eq!(
+            InlineMath::from_placeholder(code),
+            Some(InlineMath {
+                source: "a `b` c".to_string(),
+                line: 3,
+                column: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn keeps_escaped_dollars() {
+        let md = "costs \\$5, \\$x\\$ is no math";
+        assert_eq!(extract_inline_math(md), md);
+        assert_eq!(
+            extract_inline_math("$a\\$b$"),
+            math("a\\$b", 1, 1)
+        );
+    }
+
+    #[test]
+    fn keeps_invalid_delimiters() {
+        for md in ["$$x$$", "$x", "a $ x$", "$x $", "$5 and $10", "$x\ny$"] {
+            assert_eq!(extract_inline_math(md), md);
+        }
+    }
+
+    #[test]
+    fn keeps_code() {
+        for md in [
+            "`$x$`",
+            "``a ` $x$``",
+            "a `multi\nline $x$` b",
+            "```\n$x$\n```\n",
+            "~~~~\n```\n$x$\n~~~~\n",
+            "a\n\n    $x$\n",
+            "\t$x$\n",
+        ] {
+            assert_eq!(extract_inline_math(md), md);
+        }
+    }
+
+    #[test]
+    fn replaces_in_continued_lines() {
+        assert_eq!(
+            extract_inline_math("a\n    $x$\n"),
+            format!("a\n    {}\n", math("x", 2, 5))
+        );
+        assert_eq!(
+            extract_inline_math("- a\n\n    $x$\n"),
+            format!("- a\n\n    {}\n", math("x", 3, 5))
+        );
+    }
+
+    #[test]
+    fn keeps_front_matter() {
+        let md = "---\ntitle: $x$ and $y$\n---\n$z$\n";
+        assert_eq!(
+            extract_inline_math(md),
+            format!("---\ntitle: $x$ and $y$\n---\n{}\n", math("z", 4, 1))
+        );
+        // Without closing delimiter it is a thematic break and no frontmatter
+        assert_eq!(
+            extract_inline_math("---\n$x$\n"),
+            format!("---\n{}\n", math("x", 2, 1))
+        );
+        // A block that is no YAML mapping is markdown
+        assert_eq!(
+            extract_inline_math("---\nSum $x$\n---\n"),
+            format!("---\nSum {}\n---\n", math("x", 2, 5))
+        );
+    }
+
+    fn convert(source: &str) -> Result<String, String> {
+        let math = InlineMath {
+            source: source.to_string(),
+            line: 1,
+            column: 1,
+        };
+        math.convert().map(|_| math.plain_text())
+    }
+
+    #[test]
+    fn converts_fractions() {
+        assert_eq!(convert(r"\frac{1}{2}"), Ok("¹⁄₂".to_string()));
+        assert_eq!(convert(r"\frac{a}{b}"), Ok("𝑎/𝑏".to_string()));
+        assert_eq!(
+            convert(r"\frac{a+b}{2x}"),
+            Ok(format!("(𝑎{MEDIUM_SPACE}+{MEDIUM_SPACE}𝑏)/2𝑥"))
+        );
+    }
+
+    #[test]
+    fn converts_roots() {
+        assert_eq!(convert(r"\sqrt{2}"), Ok("√2".to_string()));
+        assert_eq!(convert(r"\sqrt[3]{x}"), Ok("∛𝑥".to_string()));
+        assert_eq!(
+            convert(r"\sqrt[n]{x+1}"),
+            Ok(format!("ⁿ√(𝑥{MEDIUM_SPACE}+{MEDIUM_SPACE}1)"))
+        );
+        assert!(convert(r"\sqrt[3{x}").is_err());
+    }
+
+    #[test]
+    fn degrades_unknown_commands_to_argument() {
+        assert_eq!(convert(r"\hat{x}"), Ok("𝑥".to_string()));
+        assert_eq!(convert(r"\vec v"), Ok("𝑣".to_string()));
+        assert_eq!(convert(r"\mathbf{A}\mathcal{B}"), Ok("𝐴𝐵".to_string()));
+        assert_eq!(convert(r"\overline{ab}"), Ok("𝑎𝑏".to_string()));
+        assert_eq!(convert(r"\unknown{x}"), Ok("𝑥".to_string()));
+        assert!(convert(r"\unknown").is_err());
+    }
+}
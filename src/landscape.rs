@@ -0,0 +1,60 @@
+use genpdf::{
+    elements::PageBreak, error::Error, render, style::Style, Context, Element, RenderResult,
+};
+
+use crate::tracking::RenderTracker;
+
+/// Paragraphs that only contain this marker start a section with landscape pages, which is ended
+/// by a `:::` paragraph
+pub const LANDSCAPE_START: &str = ":::landscape";
+
+/// Page break that switches the orientation of the following pages.
+///
+/// genpdf renders all pages with the same paper size, so the pages of a landscape section are
+/// laid out in a landscape shaped area on the portrait page by the page decorator. The paper size
+/// of these pages is changed while postprocessing the PDF.
+///
+/// If the break is at the top of a page, no empty page is left behind. The page was already
+/// decorated with the previous orientation though, so it's recorded for the next render pass, in
+/// which the decorator switches the orientation of the page before laying it out.
+pub struct OrientationBreak {
+    page_break: PageBreak,
+    landscape: bool,
+    tracker: RenderTracker,
+    started: bool,
+}
+
+impl OrientationBreak {
+    pub fn new(landscape: bool, tracker: &RenderTracker) -> Self {
+        Self {
+            page_break: PageBreak::new(),
+            landscape,
+            tracker: tracker.clone(),
+            started: false,
+        }
+    }
+}
+
+impl Element for OrientationBreak {
+    fn render(
+        &mut self,
+        context: &Context,
+        area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        if !self.started {
+            self.started = true;
+            if self.tracker.is_page_start(area.size().height) {
+                self.tracker.add_orientation_page(self.landscape);
+                if self.tracker.is_landscape() == self.landscape {
+                    return Ok(RenderResult::default());
+                }
+            }
+        }
+
+        // The orientation has to be set before the page break, since the next page is decorated
+        // before the rendering continues
+        self.tracker.set_landscape(self.landscape);
+        self.page_break.render(context, area, style)
+    }
+}
@@ -0,0 +1,401 @@
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use comrak::nodes::{AstNode, NodeValue};
+use genpdf::{
+    error::Error, render, style::Style, Context, Element, Mm, Position, RenderResult, Size,
+};
+use hyphenation::Standard;
+
+use crate::{attributes::Attributes, cli_args::ArgHyphenationLang, tracking::RenderTracker};
+
+/// Replaces the opening bracket of a language span, followed by the language tag and another
+/// marker. See [`mark_lang_spans`]
+const SPAN_START: char = '\u{E001}';
+/// Replaces the closing bracket and the attribute block of a language span
+const SPAN_END: char = '\u{E002}';
+
+/// The hyphenation language for a language tag like `de`, `en-GB` or `fr-CA`
+pub fn hyphenation_lang(tag: &str) -> Option<ArgHyphenationLang> {
+    let tag = tag.to_lowercase().replace('_', "-");
+    let primary = tag.split('-').next().unwrap_or("");
+
+    match (primary, tag.as_str()) {
+        (_, "en-gb") => Some(ArgHyphenationLang::EnGb),
+        ("en", _) => Some(ArgHyphenationLang::En),
+        ("de", _) => Some(ArgHyphenationLang::De),
+        ("fr", _) => Some(ArgHyphenationLang::Fr),
+        ("nl", _) => Some(ArgHyphenationLang::Nl),
+        ("es", _) => Some(ArgHyphenationLang::Es),
+        _ => None,
+    }
+}
+
+/// Load the hyphenation dictionary for the language tag. Text in languages without a dictionary
+/// is not hyphenated.
+pub fn load_hyphenation(tag: &str) -> Option<Standard> {
+    let lang = match hyphenation_lang(tag) {
+        Some(lang) => lang,
+        None => {
+            eprintln!(
+                "No hyphenation dictionary for the language '{}', it is not hyphenated",
+                tag
+            );
+            return None;
+        }
+    };
+
+    match lang.load() {
+        Ok(hyphenation) => Some(hyphenation),
+        Err(e) => {
+            eprintln!("No hyphenation for the language '{}': {}", tag, e);
+            None
+        }
+    }
+}
+
+/// A part of a text node with language span markers, see [`split_lang_spans`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpanPart<'a> {
+    Text(&'a str),
+    /// Start of a span with the language tag
+    Start(&'a str),
+    End,
+}
+
+/// Mark the spans in another language (`[Wort]{lang=de}`) in the text nodes. The brackets and the
+/// attribute block are replaced with private use characters, which are split off again with
+/// [`split_lang_spans`] while rendering. The span can contain other inline elements, but both
+/// brackets have to be in the same paragraph or inline element.
+///
+/// This has to be done before the headings are collected, so that the attribute block of a span
+/// at the end of a heading isn't taken as attributes of the heading.
+pub fn mark_lang_spans<'a>(root: &'a AstNode<'a>) {
+    let text_nodes: Vec<_> = root
+        .descendants()
+        .filter(|node| text_literal(node).is_some())
+        .collect();
+
+    for node in text_nodes {
+        let mut from = 0;
+        loop {
+            let (end, end_len, lang) =
+                match text_literal(node).and_then(|text| find_span_end(&text, from)) {
+                    Some(it) => it,
+                    None => break,
+                };
+            let (open_node, open) = match find_span_start(node, end) {
+                Some(it) => it,
+                None => {
+                    from = end + 1;
+                    continue;
+                }
+            };
+
+            // The end is replaced first, since the start can be in front of it in the same node
+            let start_marker = format!("{marker}{}{marker}", lang, marker = SPAN_START);
+            replace_text(node, end, end_len, &SPAN_END.to_string());
+            replace_text(open_node, open, 1, &start_marker);
+
+            from = end + SPAN_END.len_utf8();
+            if std::ptr::eq(open_node, node) {
+                from += start_marker.len() - 1;
+            }
+        }
+    }
+}
+
+/// The text of a text node, `None` for other nodes
+fn text_literal<'a>(node: &'a AstNode<'a>) -> Option<String> {
+    match &node.data.borrow().value {
+        NodeValue::Text(t) => Some(String::from_utf8_lossy(t).to_string()),
+        _ => None,
+    }
+}
+
+fn replace_text<'a>(node: &'a AstNode<'a>, index: usize, len: usize, with: &str) {
+    let mut text = text_literal(node).unwrap();
+    text.replace_range(index..index + len, with);
+    node.data.borrow_mut().value = NodeValue::Text(text.into_bytes());
+}
+
+/// Find the next `]{... lang=xx ...}` in the text, starting at the byte index `from`. Returns the
+/// index and length of the closing bracket with the attribute block, and the language
+fn find_span_end(text: &str, from: usize) -> Option<(usize, usize, String)> {
+    let mut from = from;
+    while let Some(offset) = text.get(from..)?.find("]{") {
+        let start = from + offset;
+        let attributes = &text[start + 2..];
+        let lang = attributes
+            .find('}')
+            .and_then(|end| Some((end, Attributes::parse(&attributes[..end])?)))
+            .and_then(|(end, attributes)| {
+                let (_, lang) = attributes.values.into_iter().find(|(key, _)| key == "lang")?;
+                Some((end, lang))
+            });
+
+        match lang {
+            Some((end, lang)) => return Some((start, end + 3, lang)),
+            None => from = start + 1,
+        }
+    }
+    None
+}
+
+/// Find the opening bracket that belongs to the closing bracket at the byte index `end` of the
+/// text node. The bracket is searched in the node and in the text nodes in front of it
+fn find_span_start<'a>(node: &'a AstNode<'a>, end: usize) -> Option<(&'a AstNode<'a>, usize)> {
+    let mut depth = 0;
+    let mut current = Some(node);
+    let mut limit = Some(end);
+
+    while let Some(candidate) = current {
+        if let Some(text) = text_literal(candidate) {
+            let text = match limit {
+                Some(limit) => &text[..limit],
+                None => &text,
+            };
+            for (index, c) in text.char_indices().rev() {
+                match c {
+                    ']' => depth += 1,
+                    '[' if depth == 0 => return Some((candidate, index)),
+                    '[' => depth -= 1,
+                    _ => (),
+                }
+            }
+        }
+        current = candidate.previous_sibling();
+        limit = None;
+    }
+
+    None
+}
+
+/// Split the text of a text node at the language span markers of [`mark_lang_spans`]
+pub fn split_lang_spans(text: &str) -> Vec<SpanPart<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+
+    while let Some(index) = rest.find([SPAN_START, SPAN_END]) {
+        if index > 0 {
+            parts.push(SpanPart::Text(&rest[..index]));
+        }
+        let marker = rest[index..].chars().next().unwrap();
+        rest = &rest[index + marker.len_utf8()..];
+
+        if marker == SPAN_END {
+            parts.push(SpanPart::End);
+            continue;
+        }
+        match rest.split_once(SPAN_START) {
+            Some((lang, remaining)) => {
+                parts.push(SpanPart::Start(lang));
+                rest = remaining;
+            }
+            None => break,
+        }
+    }
+    if !rest.is_empty() {
+        parts.push(SpanPart::Text(rest));
+    }
+
+    parts
+}
+
+/// Remove the language span markers, for the plain text of a node
+pub fn strip_lang_spans(text: &str) -> String {
+    split_lang_spans(text)
+        .into_iter()
+        .filter_map(|part| match part {
+            SpanPart::Text(text) => Some(text),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parse the language of a language block marker (`::: {lang=de}`)
+pub fn parse_lang_marker(text: &str) -> Option<String> {
+    let attributes = text.strip_prefix(":::")?.trim();
+    let attributes = attributes.strip_prefix('{')?.strip_suffix('}')?;
+    Attributes::parse(attributes)?
+        .values
+        .into_iter()
+        .find(|(key, _)| key == "lang")
+        .map(|(_, lang)| lang)
+}
+
+/// The hyphenation dictionary of a language, shared by all elements in that language. The
+/// dictionary is `None` for languages that are not hyphenated.
+///
+/// genpdf takes the hyphenator from the render context, so the elements are rendered with a copy
+/// of the context that has the dictionary. The copy is only made on the first render and then
+/// reused, since the context doesn't change while the document is rendered.
+pub struct Hyphenator {
+    lang: String,
+    dictionary: Option<Standard>,
+    context: RefCell<Option<Rc<Context>>>,
+}
+
+impl Hyphenator {
+    fn new(lang: &str, dictionary: Option<Standard>) -> Self {
+        Self {
+            lang: lang.to_string(),
+            dictionary,
+            context: RefCell::new(None),
+        }
+    }
+
+    /// The language tag, like `de`
+    pub fn lang(&self) -> &str {
+        &self.lang
+    }
+
+    pub fn dictionary(&self) -> Option<&Standard> {
+        self.dictionary.as_ref()
+    }
+
+    /// The render context with the dictionary of the language
+    fn context(&self, context: &Context) -> Rc<Context> {
+        self.context
+            .borrow_mut()
+            .get_or_insert_with(|| {
+                let mut context = context.clone();
+                context.hyphenator = self.dictionary.clone();
+                Rc::new(context)
+            })
+            .clone()
+    }
+}
+
+impl fmt::Debug for Hyphenator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hyphenator")
+            .field("lang", &self.lang)
+            .field("hyphenated", &self.dictionary.is_some())
+            .finish()
+    }
+}
+
+/// The hyphenators of the languages used in the document. Every dictionary is only loaded once
+/// for all render passes, the hyphenators are created again in every pass since they keep the
+/// render context of the pass.
+#[derive(Default)]
+pub struct Hyphenators {
+    dictionaries: HashMap<String, Option<Standard>>,
+    hyphenators: HashMap<String, Rc<Hyphenator>>,
+}
+
+impl Hyphenators {
+    /// Drop the hyphenators of the previous render pass
+    pub fn start_pass(&mut self) {
+        self.hyphenators.clear();
+    }
+
+    pub fn get(&mut self, tag: &str) -> Rc<Hyphenator> {
+        let dictionaries = &mut self.dictionaries;
+        self.hyphenators
+            .entry(tag.to_string())
+            .or_insert_with(|| {
+                let dictionary = dictionaries
+                    .entry(tag.to_string())
+                    .or_insert_with(|| load_hyphenation(tag))
+                    .clone();
+                Rc::new(Hyphenator::new(tag, dictionary))
+            })
+            .clone()
+    }
+}
+
+/// Element that is rendered with a different hyphenator than the rest of the document, for text
+/// in another language.
+///
+/// genpdf hyphenates a paragraph with the hyphenator of the render context, so the language is
+/// switched for the whole element. Spans in another language inside of a paragraph are
+/// hyphenated by the [`TextBlock`](crate::text_block::TextBlock). The rendered areas are
+/// recorded, so that the language of the text can be stored in the PDF.
+pub struct WithHyphenator<E: Element> {
+    element: E,
+    hyphenator: Rc<Hyphenator>,
+    tracker: RenderTracker,
+}
+
+impl<E: Element> WithHyphenator<E> {
+    pub fn new(element: E, hyphenator: Rc<Hyphenator>, tracker: &RenderTracker) -> Self {
+        Self {
+            element,
+            hyphenator,
+            tracker: tracker.clone(),
+        }
+    }
+}
+
+impl<E: Element> Element for WithHyphenator<E> {
+    fn render(
+        &mut self,
+        context: &Context,
+        area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        let available = area.size();
+        let context = self.hyphenator.context(context);
+        let result = self.element.render(&context, area, style)?;
+
+        if result.size.height > Mm(0.0) {
+            let size = Size::new(available.width, result.size.height);
+            let rect = self.tracker.locate(available, Position::new(0, 0), size);
+            self.tracker.add_lang_area(rect, self.hyphenator.lang());
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hyphenation_languages() {
+        assert_eq!(hyphenation_lang("de"), Some(ArgHyphenationLang::De));
+        assert_eq!(hyphenation_lang("de-AT"), Some(ArgHyphenationLang::De));
+        assert_eq!(hyphenation_lang("en"), Some(ArgHyphenationLang::En));
+        assert_eq!(hyphenation_lang("en-US"), Some(ArgHyphenationLang::En));
+        assert_eq!(hyphenation_lang("en_GB"), Some(ArgHyphenationLang::EnGb));
+        assert_eq!(hyphenation_lang("fr-CA"), Some(ArgHyphenationLang::Fr));
+        assert_eq!(hyphenation_lang("ja"), None);
+        assert_eq!(hyphenation_lang(""), None);
+    }
+
+    #[test]
+    fn split_spans() {
+        let text = format!("a {s}de{s}Wort{e} b", s = SPAN_START, e = SPAN_END);
+        assert_eq!(
+            split_lang_spans(&text),
+            vec![
+                SpanPart::Text("a "),
+                SpanPart::Start("de"),
+                SpanPart::Text("Wort"),
+                SpanPart::End,
+                SpanPart::Text(" b"),
+            ]
+        );
+        assert_eq!(strip_lang_spans(&text), "a Wort b");
+        assert_eq!(split_lang_spans("plain"), vec![SpanPart::Text("plain")]);
+    }
+
+    #[test]
+    fn span_end() {
+        assert_eq!(find_span_end("[Wort]{lang=de} x", 0), Some((5, 10, "de".to_string())));
+        assert_eq!(find_span_end("[a]{.note lang=fr}", 0), Some((2, 16, "fr".to_string())));
+        assert_eq!(find_span_end("[a]{.note} [b]{lang=nl}", 0), Some((13, 10, "nl".to_string())));
+        assert_eq!(find_span_end("[a]{lang=de", 0), None);
+        assert_eq!(find_span_end("[a](b)", 0), None);
+    }
+
+    #[test]
+    fn lang_marker() {
+        assert_eq!(parse_lang_marker("::: {lang=de}"), Some("de".to_string()));
+        assert_eq!(parse_lang_marker(":::{.note lang=fr}"), Some("fr".to_string()));
+        assert_eq!(parse_lang_marker("::: {.note}"), None);
+        assert_eq!(parse_lang_marker(":::"), None);
+    }
+}
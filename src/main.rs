@@ -1,33 +1,251 @@
+mod attributes;
 mod base_style;
 mod cli_args;
+mod columns;
+mod config;
+mod elements;
+mod equations;
+mod font_files;
+mod footnotes;
+mod glyph_fallback;
+mod header_footer;
+mod headings;
+mod inline_math;
+mod landscape;
+mod language;
+mod numbering;
+mod postprocess;
 mod resources;
+mod text_block;
+mod title;
+mod toc;
+mod tracking;
 
-use std::{fs::File, io::BufReader, path::Path};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use clap::Parser;
-use comrak::{arena_tree::NodeEdge, nodes::NodeValue, Arena};
+use comrak::{
+    arena_tree::NodeEdge,
+    nodes::{AstNode, ListDelimType, ListType, NodeValue, TableAlignment},
+    Arena,
+};
 use genpdf::{
-    elements::{Image, PaddedElement, PageBreak, Paragraph, UnorderedList, Math, CodeBlock},
-    fonts::FontData,
+    elements::{
+        BulletPoint, Image, LinearLayout, PaddedElement, PageBreak, Math, CodeBlock,
+    },
+    fonts::{FontData, FontFamily},
     style::{Color, Style, StyledString, LineStyle},
     Alignment, Margins, Scale, syntax_highlighting::SyntaxHighlighter, Element,
 };
 
-use crate::{base_style::DocumentStyle, cli_args::CliArgs};
+use crate::{
+    base_style::{DocumentStyle, TableRules},
+    cli_args::CliArgs,
+    columns::ColumnFlow,
+    config::{ProjectConfig, StyleConfig},
+    elements::{ChapterBreak, CheckboxItem, NumberedEquation, Table, TableCell},
+    equations::{Equation, TextPart},
+    font_files::FontFiles,
+    footnotes::Footnotes,
+    glyph_fallback::{FallbackFont, GlyphCoverage, GlyphFallback, TextCoverage},
+    headings::Heading,
+    inline_math::InlineMath,
+    landscape::OrientationBreak,
+    language::{Hyphenator, Hyphenators, SpanPart},
+    numbering::NumberingStyle,
+    text_block::{TextBlock, TextRun},
+    tracking::{LayoutInfo, LinkTarget, RenderTracker, TrackId, Tracked},
+};
+
+/// A list that is built up while traversing the list items. Ordered and unordered lists are both
+/// made of bullet points, the only difference is the bullet text
+struct ListBuilder {
+    layout: LinearLayout,
+    /// Numbering style and delimiter for ordered lists, `None` for unordered lists
+    numbering: Option<(NumberingStyle, ListDelimType)>,
+    next_number: usize,
+    /// Only the first element of an item gets a bullet, following paragraphs and nested lists
+    /// of the same item are just indented
+    item_started: bool,
+    /// Set if the current item is a task list item, the value is the checked state
+    task_item: Option<bool>,
+}
+
+impl ListBuilder {
+    pub fn unordered() -> Self {
+        Self {
+            layout: LinearLayout::vertical(),
+            numbering: None,
+            next_number: 1,
+            item_started: false,
+            task_item: None,
+        }
+    }
+
+    pub fn ordered(start: usize, delimiter: ListDelimType, style: NumberingStyle) -> Self {
+        Self {
+            layout: LinearLayout::vertical(),
+            numbering: Some((style, delimiter)),
+            next_number: start,
+            item_started: false,
+            task_item: None,
+        }
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        self.numbering.is_some()
+    }
+
+    pub fn start_item(&mut self) {
+        self.item_started = true;
+    }
+
+    /// Mark the current item as task list item, so it gets a checkbox instead of a bullet
+    pub fn start_task_item(&mut self, checked: bool) {
+        self.item_started = true;
+        self.task_item = Some(checked);
+    }
+
+    pub fn push(&mut self, element: impl Element + 'static) {
+        if !self.item_started {
+            self.push_no_bullet(element);
+            return;
+        }
+        self.item_started = false;
+
+        // Task items in ordered lists keep their number in front of the checkbox
+        if let Some(checked) = self.task_item.take() {
+            let item = CheckboxItem::new(element, checked);
+            match self.numbering {
+                Some(_) => {
+                    let bullet = self.next_bullet();
+                    self.layout.push(BulletPoint::new(item).with_bullet(bullet));
+                }
+                None => self.layout.push(item),
+            }
+            return;
+        }
+
+        match self.numbering {
+            Some(_) => {
+                let bullet = self.next_bullet();
+                self.layout.push(BulletPoint::new(element).with_bullet(bullet));
+            }
+            None => self.layout.push(BulletPoint::new(element)),
+        }
+    }
+
+    /// The bullet text of the next item. Ordered lists advance to the next number
+    pub fn next_bullet(&mut self) -> String {
+        match self.numbering {
+            Some((style, delimiter)) => {
+                let delimiter = match delimiter {
+                    ListDelimType::Period => '.',
+                    ListDelimType::Paren => ')',
+                };
+                let bullet = format!("{}{}", style.format(self.next_number), delimiter);
+                self.next_number += 1;
+                bullet
+            }
+            None => "–".to_string(),
+        }
+    }
+
+    pub fn push_no_bullet(&mut self, element: impl Element + 'static) {
+        self.layout.push(BulletPoint::new(element).with_bullet(""));
+    }
+}
+
+/// A table that is built up row by row while traversing the table cells
+struct TableBuilder {
+    alignments: Vec<TableAlignment>,
+    header_rows: usize,
+    rows: Vec<Vec<TableCell>>,
+    current_row: Vec<TableCell>,
+}
+
+impl TableBuilder {
+    pub fn new(alignments: Vec<TableAlignment>) -> Self {
+        Self {
+            alignments,
+            header_rows: 0,
+            rows: Vec::new(),
+            current_row: Vec::new(),
+        }
+    }
+
+    /// Alignment of the cell that is started next
+    pub fn next_cell_alignment(&self) -> Alignment {
+        match self.alignments.get(self.current_row.len()) {
+            Some(TableAlignment::Center) => Alignment::Center,
+            Some(TableAlignment::Right) => Alignment::Right,
+            _ => Alignment::Left,
+        }
+    }
+
+    pub fn push_cell(&mut self, cell: TableCell) {
+        self.current_row.push(cell);
+    }
+
+    pub fn finish_row(&mut self, header: bool) {
+        let mut row = std::mem::take(&mut self.current_row);
+        // Every row needs exactly one cell per column
+        row.resize_with(self.alignments.len().max(1), || TableCell::new(Alignment::Left));
+        self.rows.push(row);
+
+        if header {
+            self.header_rows = self.rows.len();
+        }
+    }
+
+    pub fn build(mut self, rules: TableRules, tracker: &RenderTracker) -> Table {
+        let body = self.rows.split_off(self.header_rows);
+        Table::new(self.alignments.len().max(1), self.rows, body, rules, tracker)
+    }
+}
 
 struct FormatStack {
     styles: Vec<Style>,
-    paragraphs: Vec<Paragraph>,
-    lists: Vec<UnorderedList>,
+    paragraphs: Vec<TextBlock>,
+    lists: Vec<ListBuilder>,
+    table: Option<TableBuilder>,
+    /// The table cell that is currently collected
+    table_cell: Option<TableCell>,
+    /// Targets of the links that are open, the text belongs to the innermost one
+    links: Vec<LinkTarget>,
+    /// Underline the text of links
+    link_underline: bool,
+    /// Background of the text that is pushed, used for inline code
+    background: Option<Color>,
+    /// Languages of the spans in another language that are open, the text belongs to the
+    /// innermost one
+    langs: Vec<Rc<Hyphenator>>,
+    /// Anchors (e.g. of footnote references) that were added since the last element was finished
+    pending_anchors: Vec<String>,
+    /// Paragraphs of the footnote definition that is currently collected
+    footnote: Option<Vec<Vec<StyledString>>>,
     blockquote_active: bool,
 }
 
 impl FormatStack {
-    pub fn new(default_style: Style) -> Self {
+    pub fn new(default_style: Style, link_underline: bool) -> Self {
         Self {
             styles: vec![default_style],
             paragraphs: Vec::new(),
             lists: Vec::new(),
+            table: None,
+            table_cell: None,
+            links: Vec::new(),
+            link_underline,
+            background: None,
+            langs: Vec::new(),
+            pending_anchors: Vec::new(),
+            footnote: None,
             blockquote_active: false,
         }
     }
@@ -46,23 +264,115 @@ impl FormatStack {
         self.styles.last().unwrap().clone()
     }
 
-    pub fn push_paragraph(&mut self, p: Paragraph) {
+    pub fn push_paragraph(&mut self, p: TextBlock) {
         self.paragraphs.push(p);
     }
 
-    pub fn pop_paragraph(&mut self) -> Paragraph {
+    pub fn pop_paragraph(&mut self) -> TextBlock {
         self.paragraphs.pop().unwrap()
     }
 
-    pub fn get_paragraph_mut(&mut self) -> &mut Paragraph {
+    pub fn get_paragraph_mut(&mut self) -> &mut TextBlock {
         self.paragraphs.last_mut().unwrap()
     }
 
-    pub fn push_list(&mut self, p: UnorderedList) {
+    /// A run with the text that links to the target of the innermost open link and has the
+    /// language of the innermost open span
+    fn make_run(&self, s: StyledString) -> TextRun {
+        let mut run = TextRun::new(s);
+        run.link = self.links.last().cloned();
+        run.underline = run.link.is_some() && self.link_underline;
+        run.lang = self.langs.last().cloned();
+        run.background = self.background;
+        run
+    }
+
+    /// Push text to the currently collected footnote or open table cell, or to the current
+    /// paragraph if there is neither. Footnotes only keep the text, links in footnotes can't be
+    /// located.
+    pub fn push_text(&mut self, s: StyledString) {
+        if let Some(paragraph) = self.footnote.as_mut().and_then(|it| it.last_mut()) {
+            paragraph.push(s);
+            return;
+        }
+        let run = self.make_run(s);
+        match self.table_cell.as_mut() {
+            Some(cell) => cell.push(run),
+            None => self.get_paragraph_mut().push(run),
+        }
+    }
+
+    /// Push a space, unless the text is empty or already ends with a space
+    pub fn push_space(&mut self) {
+        let space = StyledString::new(' ', self.get_style());
+
+        match self.footnote.as_mut().and_then(|it| it.last_mut()) {
+            Some(paragraph) => {
+                if paragraph.last().map(|w| !w.s.ends_with(' ')).unwrap_or(false) {
+                    paragraph.push(space);
+                }
+            }
+            None => {
+                let space = self.make_run(space);
+                self.get_paragraph_mut().push_space(space);
+            }
+        }
+    }
+
+    /// Draw the background behind the text that is pushed until it is reset with `None`
+    pub fn set_background(&mut self, background: Option<Color>) {
+        self.background = background;
+    }
+
+    /// Start a span in another language (`[Wort]{lang=de}`)
+    pub fn push_lang(&mut self, lang: Rc<Hyphenator>) {
+        self.langs.push(lang);
+    }
+
+    pub fn pop_lang(&mut self) {
+        self.langs.pop();
+    }
+
+    pub fn in_footnote(&self) -> bool {
+        self.footnote.is_some()
+    }
+
+    /// Start collecting the text of a footnote definition. The text is collected in the footnote
+    /// size, so that sizes relative to the current style (e.g. of math scripts) match the notes.
+    pub fn start_footnote(&mut self, font_size: u8) {
+        self.push_style(|s| {
+            s.set_font_size(font_size);
+        });
+        self.footnote = Some(vec![Vec::new()]);
+    }
+
+    /// Start a new paragraph in the footnote, for block content like list items and code lines
+    pub fn footnote_break(&mut self) {
+        if let Some(footnote) = self.footnote.as_mut() {
+            if footnote.last().map(|it| !it.is_empty()).unwrap_or(true) {
+                footnote.push(Vec::new());
+            }
+        }
+    }
+
+    /// Finish the footnote definition and return the collected paragraphs without trailing space
+    pub fn finish_footnote(&mut self) -> Vec<Vec<StyledString>> {
+        self.pop_style();
+        let mut paragraphs = self.footnote.take().unwrap();
+        for text in paragraphs.iter_mut() {
+            if text.last().map(|w| w.s.trim().is_empty()).unwrap_or(false) {
+                text.pop();
+            }
+        }
+        paragraphs.retain(|text| !text.is_empty());
+        paragraphs
+    }
+
+    pub fn push_list(&mut self, p: ListBuilder) {
         self.lists.push(p);
     }
 
-    pub fn pop_list(&mut self) -> UnorderedList {
+    pub fn pop_list(&mut self) -> ListBuilder {
         self.lists.pop().unwrap()
     }
 
@@ -70,9 +380,66 @@ impl FormatStack {
         !self.lists.is_empty()
     }
 
-    pub fn get_list_mut(&mut self) -> &mut UnorderedList {
+    /// The number of ordered lists that are currently open, used to select the numbering style
+    pub fn ordered_list_depth(&self) -> usize {
+        self.lists.iter().filter(|it| it.is_ordered()).count()
+    }
+
+    pub fn get_list_mut(&mut self) -> &mut ListBuilder {
         self.lists.last_mut().unwrap()
     }
+
+    pub fn get_table_mut(&mut self) -> &mut TableBuilder {
+        self.table.as_mut().unwrap()
+    }
+
+    /// Start a link, the following text links to the target until [`FormatStack::pop_link`]
+    pub fn push_link(&mut self, target: LinkTarget) {
+        self.links.push(target);
+    }
+
+    pub fn pop_link(&mut self) {
+        self.links.pop();
+    }
+
+    pub fn push_anchor(&mut self, name: String) {
+        self.pending_anchors.push(name);
+    }
+
+    /// Wrap a finished element for tracking and attach the anchors that were added since the
+    /// last finished element, so that they can be located after rendering
+    pub fn track_element<E: Element>(
+        &mut self,
+        tracker: &RenderTracker,
+        element: E,
+    ) -> (Tracked<E>, TrackId) {
+        let (tracked, id) = tracker.track(element);
+        for name in self.pending_anchors.drain(..) {
+            tracker.add_anchor(id, &name);
+        }
+        (tracked, id)
+    }
+}
+
+/// Collect the plain text of all text and code nodes inside of the node
+fn node_text<'a>(node: &'a AstNode<'a>) -> String {
+    let mut text = String::new();
+    for child in node.descendants() {
+        match &child.data.borrow().value {
+            NodeValue::Text(t) => {
+                text.push_str(&language::strip_lang_spans(&String::from_utf8_lossy(t)))
+            }
+            NodeValue::Code(code) => {
+                let code = String::from_utf8_lossy(&code.literal);
+                match InlineMath::from_placeholder(&code) {
+                    Some(math) => text.push_str(&math.plain_text()),
+                    None => text.push_str(&code),
+                }
+            }
+            _ => (),
+        }
+    }
+    text
 }
 
 enum NodeStartEnd {
@@ -80,15 +447,7 @@ enum NodeStartEnd {
     End,
 }
 
-const EMBEDDED_DEFAULT_FONT: [&str; 4] = [
-    resources::FONT_REGULAR,
-    resources::FONT_BOLD,
-    resources::FONT_ITALIC,
-    resources::FONT_BOLDITALIC,
-];
-
-fn make_font_family(data: &[u8]) -> genpdf::fonts::FontFamily<FontData> {
-    let font = genpdf::fonts::FontData::new(data.to_vec(), None).unwrap().with_subsetting(true);
+fn make_font_family(font: FontData) -> genpdf::fonts::FontFamily<FontData> {
     genpdf::fonts::FontFamily {
         regular: font.clone(),
         bold: font.clone(),
@@ -97,63 +456,329 @@ fn make_font_family(data: &[u8]) -> genpdf::fonts::FontFamily<FontData> {
     }
 }
 
-fn main() {
-    // Cli Parsing and base style setup
-    let cli_args = CliArgs::parse();
-    let docstyle = DocumentStyle::from(&cli_args);
+/// The fonts are only loaded once and then cloned for every render pass
+struct Fonts {
+    text: FontFamily<FontData>,
+    monospace: FontFamily<FontData>,
+    math: FontFamily<FontData>,
+    math_raw: Vec<u8>,
+    /// Glyph coverage of the text and monospace fonts, to decide when the fallback fonts are used
+    text_coverage: TextCoverage,
+    monospace_coverage: GlyphCoverage,
+    fallbacks: Vec<FallbackFont>,
+}
 
-    let md = std::fs::read_to_string(&cli_args.input).expect("Can't read input file");
+/// Load the font files, or the embedded fonts for the fonts that are not set
+fn load_fonts(files: &FontFiles, allow_subsetting: bool) -> Result<Fonts, String> {
+    // Styles without a font file use the regular font file, so that fonts aren't mixed
+    files.check()?;
 
-    let allow_subsetting = !cli_args.disable_font_subsetting;
+    // The glyph coverage is read from the raw data of every style
+    let load_text_font = |file: &Option<String>, embedded: &str| {
+        let file = file.as_deref().or(files.font_regular.as_deref());
+        let raw = font_files::read_font(file, embedded)?;
+        let coverage = GlyphCoverage::new(&raw)?;
+        Ok::<_, String>((font_files::parse_font(raw, file)?, coverage))
+    };
 
-    // PDF document setup
-    let [regular, bold, italic, bold_italic] = EMBEDDED_DEFAULT_FONT.map(|font| {
-        resources::get_decompress(font)
-    });
+    let (regular, regular_coverage) = load_text_font(&files.font_regular, resources::FONT_REGULAR)?;
+    let (bold, bold_coverage) = load_text_font(&files.font_bold, resources::FONT_BOLD)?;
+    let (italic, italic_coverage) = load_text_font(&files.font_italic, resources::FONT_ITALIC)?;
+    let (bold_italic, bold_italic_coverage) =
+        load_text_font(&files.font_bold_italic, resources::FONT_BOLDITALIC)?;
+    let text_coverage = TextCoverage {
+        regular: regular_coverage,
+        bold: bold_coverage,
+        italic: italic_coverage,
+        bold_italic: bold_italic_coverage,
+    };
 
     let font = genpdf::fonts::FontFamily {
-        regular: FontData::new(regular, None).unwrap(),
-        bold: FontData::new(bold, None).unwrap(),
-        italic: FontData::new(italic, None).unwrap(),
-        bold_italic: FontData::new(bold_italic, None).unwrap(),
+        regular,
+        bold,
+        italic,
+        bold_italic,
     }.with_subsetting(allow_subsetting);
 
-    let mut monospace_font = make_font_family(&resources::get_decompress(resources::FONT_MONOSPACE))
-        .with_subsetting(allow_subsetting);
-    monospace_font.bold = FontData::new(resources::get_decompress(resources::FONT_MONOSPACE_BOLD), None).unwrap();
+    let monospace_file = files.font_mono.as_deref();
+    let monospace_raw = font_files::read_font(monospace_file, resources::FONT_MONOSPACE)?;
+    let monospace_coverage = GlyphCoverage::new(&monospace_raw)?;
+    let mut monospace_font = make_font_family(
+        font_files::parse_font(monospace_raw, monospace_file)?
+    );
+    if monospace_file.is_none() {
+        monospace_font.bold = font_files::load_font(None, resources::FONT_MONOSPACE_BOLD)?;
+    }
+    let monospace_font = monospace_font.with_subsetting(allow_subsetting);
 
-    let font_raw = resources::get_decompress(resources::FONT_MATH);
-    let math_font_family = make_font_family(&font_raw).with_subsetting(allow_subsetting);
+    let font_raw = font_files::read_font(files.font_math.as_deref(), resources::FONT_MATH)?;
+    if let Some(path) = &files.font_math {
+        font_files::check_math_table(&font_raw, path)?;
+    }
+    let math_font = FontData::new(font_raw.clone(), None).map_err(|e| {
+        format!("Can't load math font: {}", e)
+    })?;
+    let math_font_family = make_font_family(math_font).with_subsetting(allow_subsetting);
 
-    let mut doc = genpdf::Document::new(font);
-    doc.set_minimal_conformance();
-    let monospace_font = doc.add_font_family(monospace_font);
-    let math_font_family = doc.add_font_family(math_font_family);
-    doc.enable_math(&font_raw, math_font_family);
-    doc.enable_syntax_highlighting(SyntaxHighlighter::load_defaults());
-    docstyle.apply_base_style(&mut doc);
+    let fallbacks = files
+        .font_fallback
+        .iter()
+        .map(|path| FallbackFont::load(path, allow_subsetting))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Fonts {
+        text: font,
+        monospace: monospace_font,
+        math: math_font_family,
+        math_raw: font_raw,
+        text_coverage,
+        monospace_coverage,
+        fallbacks,
+    })
+}
+
+/// Documents that depend on their own layout (e.g. page numbers in the table of contents) are
+/// rendered again until the layout doesn't change anymore, but at most this often
+const MAX_RENDER_PASSES: usize = 4;
+
+/// Paragraphs that only contain this marker end the innermost open container directive
+const CONTAINER_END: &str = ":::";
+
+/// Container directives (`:::name` paragraphs) that change the layout of the enclosed content
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Container {
+    /// See [`landscape::LANDSCAPE_START`]
+    Landscape,
+    /// See [`columns::COLUMNS_START`]
+    Columns,
+    /// Content in another language (`::: {lang=de}`), see [`language::parse_lang_marker`]
+    Lang(String),
+}
+
+/// The hyphenator of the innermost language block, `None` if the content is in the document
+/// language. Language containers take precedence over the language of the section.
+fn block_hyphenator(
+    docstyle: &DocumentStyle,
+    containers: &[Container],
+    section_langs: &[(u8, String)],
+    hyphenators: &mut Hyphenators,
+) -> Option<Rc<Hyphenator>> {
+    let lang = containers
+        .iter()
+        .rev()
+        .find_map(|container| match container {
+            Container::Lang(lang) => Some(lang),
+            _ => None,
+        })
+        .or_else(|| section_langs.last().map(|(_, lang)| lang))?;
+
+    match docstyle.lang.as_ref() == Some(lang) {
+        true => None,
+        false => Some(hyphenators.get(lang)),
+    }
+}
+
+fn main() {
+    // Cli Parsing and base style setup
+    let cli_args = CliArgs::parse();
+    let input = Path::new(&cli_args.input);
+
+    let project = match ProjectConfig::load(input, cli_args.profile.as_deref()) {
+        Ok(project) => project,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let output = match &cli_args.output {
+        Some(output) => PathBuf::from(output),
+        None => project
+            .as_ref()
+            .and_then(ProjectConfig::output)
+            .unwrap_or_else(|| input.with_extension("pdf")),
+    };
+
+    let md = std::fs::read_to_string(input).expect("Can't read input file");
+    let md = inline_math::extract_inline_math(&md);
+
+    let disable_subsetting = project
+        .as_ref()
+        .map(ProjectConfig::disable_font_subsetting)
+        .unwrap_or(false);
+    let allow_subsetting = !(cli_args.disable_font_subsetting || disable_subsetting);
+
+    // Font setup, the font files from the command line take precedence over the project config
+    let font_files = cli_args
+        .fonts
+        .clone()
+        .or(project.as_ref().map(ProjectConfig::fonts).unwrap_or_default());
+    let fonts = match load_fonts(&font_files, allow_subsetting) {
+        Ok(fonts) => fonts,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // Markdown parsing
     let arena = Arena::new();
     let mut opts = comrak::ComrakOptions::default();
-    // opts.extension.tasklist = true;
+    opts.extension.tasklist = true;
     opts.extension.strikethrough = true;
-    let md_ast = comrak::parse_document(&arena, &md, &opts);
+    opts.extension.table = true;
+    opts.extension.autolink = true;
+    opts.extension.footnotes = true;
+    opts.extension.front_matter_delimiter = Some("---".to_string());
+    let mut md_ast = comrak::parse_document(&arena, &md, &opts);
+    if config::has_invalid_frontmatter(md_ast) {
+        // The `---` block is a thematic break followed by markdown
+        opts.extension.front_matter_delimiter = None;
+        md_ast = comrak::parse_document(&arena, &md, &opts);
+    }
+    language::mark_lang_spans(md_ast);
+
+    // Style setup, see `ProjectConfig` for the order in which the settings are applied
+    let frontmatter = StyleConfig::from_document(md_ast);
+    let docstyle = config::document_style(project.as_ref(), frontmatter.as_ref(), &cli_args);
 
-    let mut stylestack = FormatStack::new(Style::default());
+    let mut headings = headings::collect_headings(md_ast);
+    if docstyle.heading_numbering {
+        headings::assign_numbers(&mut headings, docstyle.numbering_start_level);
+    }
+    let equations =
+        equations::collect_equations(md_ast, &headings, docstyle.equation_numbering);
+
+    // Rendering. The hyphenation dictionaries of other languages are only loaded once for all
+    // passes
+    let mut hyphenators = Hyphenators::default();
+    let mut layout: Option<LayoutInfo> = None;
+    let mut pass = 1;
+    let (pdf, tracker, footnotes, glyph_fallback) = loop {
+        let (doc, tracker, footnotes, glyph_fallback, needs_layout) = build_document(
+            &cli_args,
+            &docstyle,
+            &fonts,
+            md_ast,
+            &headings,
+            &equations,
+            &mut hyphenators,
+            layout.as_ref(),
+        );
+
+        let mut pdf = Vec::new();
+        doc.render(&mut pdf).unwrap();
+
+        let new_layout = tracker.layout_info();
+        let stable = layout.as_ref() == Some(&new_layout);
+        if !needs_layout || stable || pass == MAX_RENDER_PASSES {
+            break (pdf, tracker, footnotes, glyph_fallback);
+        }
+
+        layout = Some(new_layout);
+        pass += 1;
+    };
+
+    // Characters that no font covers are printed as missing glyph boxes
+    let missing = glyph_fallback.missing();
+    if !missing.is_empty() {
+        eprintln!(
+            "No font has glyphs for the characters '{}', add a font that has them with \
+            --font-fallback",
+            missing.into_iter().collect::<String>()
+        );
+    }
+
+    if footnotes.carried_over() > 0 {
+        eprintln!("The last footnotes don't fit on the last page and are missing");
+    }
+
+    let pdf = postprocess::postprocess(&pdf, &tracker, &headings, &docstyle).unwrap();
+    std::fs::write(output, pdf).expect("Can't write output file");
+}
+
+/// Create the PDF document from the markdown AST. The layout of the previous render pass is
+/// used for content that depends on the final layout. The returned flag is set if such content
+/// is part of the document, so another pass with the new layout is required. The footnotes and
+/// the glyph fallback are returned to check if all footnotes were printed and all characters
+/// had a font.
+///
+/// The document is traversed again in every pass, so diagnostics are only printed in the first
+/// pass.
+#[allow(clippy::too_many_arguments)]
+fn build_document<'a>(
+    cli_args: &CliArgs,
+    docstyle: &DocumentStyle,
+    fonts: &Fonts,
+    md_ast: &'a AstNode<'a>,
+    headings: &[Heading],
+    equations: &[Equation],
+    hyphenators: &mut Hyphenators,
+    layout: Option<&LayoutInfo>,
+) -> (genpdf::Document, RenderTracker, Footnotes, GlyphFallback, bool) {
+    let first_pass = layout.is_none();
+    hyphenators.start_pass();
+    let mut doc = genpdf::Document::new(fonts.text.clone());
+    doc.set_minimal_conformance();
+    let monospace_font = doc.add_font_family(fonts.monospace.clone());
+    let math_font_family = doc.add_font_family(fonts.math.clone());
+    doc.enable_math(&fonts.math_raw, math_font_family);
+    doc.enable_syntax_highlighting(SyntaxHighlighter::load_defaults());
+    let fallback_families = fonts
+        .fallbacks
+        .iter()
+        .map(|font| doc.add_font_family(font.family.clone()))
+        .collect();
+    let glyph_fallback = GlyphFallback::new(
+        &fonts.text_coverage,
+        &fonts.monospace_coverage,
+        &fonts.fallbacks,
+        fallback_families,
+    );
+    let tracker = RenderTracker::new();
+
+    // comrak moves the footnote definitions to the end of the document, ordered by their first
+    // reference
+    let footnote_definitions = md_ast
+        .descendants()
+        .filter_map(|node| match &node.data.borrow().value {
+            NodeValue::FootnoteDefinition(name) => Some(String::from_utf8_lossy(name).to_string()),
+            _ => None,
+        })
+        .collect();
+    let footnotes = Footnotes::new(footnote_definitions, layout.cloned(), monospace_font);
+    docstyle.apply_base_style(&mut doc, &tracker, &footnotes, &glyph_fallback, headings, layout);
+
+    let mut heading_index = 0;
+    let mut equation_index = 0;
+    // Container directives that are open, closed by a `:::` paragraph
+    let mut containers: Vec<Container> = Vec::new();
+    // Languages of the headings (`{lang=de}`) that apply to their sections, with the level
+    let mut section_langs: Vec<(u8, String)> = Vec::new();
+    let mut flow = ColumnFlow::new(docstyle, &tracker);
+    let mut needs_layout = docstyle.header_footer_uses_layout();
+
+    title::push_title(&mut doc, docstyle, &glyph_fallback);
+
+    // The font size is set explicitly, so that relative sizes (e.g. for math scripts) can be
+    // calculated from the current style
+    let mut stylestack = FormatStack::new(
+        Style::new().with_font_size(docstyle.text_size),
+        docstyle.link_underline,
+    );
 
     // Markdown AST traversal to create matching PDF outputs to the markdown elements
     for node_edge in md_ast.traverse() {
         use NodeStartEnd::{End, Start};
 
-        let (ast_node, start) = match node_edge {
-            NodeEdge::Start(it) => (&it.data, Start),
-            NodeEdge::End(it) => (&it.data, End),
+        let (arena_node, start) = match node_edge {
+            NodeEdge::Start(it) => (it, Start),
+            NodeEdge::End(it) => (it, End),
         };
+        let ast_node = &arena_node.data;
         let node = &ast_node.borrow().value;
 
-        // Debug prints for the AST Nodes
-        if cli_args.print_ast {
+        // Debug prints for the AST Nodes, only in the first render pass
+        if cli_args.print_ast && first_pass {
             match start {
                 Start => print!("START: "),
                 End => print!("END: "),
@@ -166,24 +791,95 @@ fn main() {
 
         match (start, node) {
                 (Start, NodeValue::Paragraph) => {
-                    let mut p = Paragraph::default();
+                    let mut p = TextBlock::new(&tracker);
                     if docstyle.align_justify {
                         p.set_alignment(Alignment::Justified(true));
                     }
                     stylestack.push_paragraph(p);
                 }
                 (Start, NodeValue::Heading(h)) => {
+                    section_langs.retain(|(level, _)| *level < h.level);
+                    if let Some(lang) = &headings[heading_index].lang {
+                        section_langs.push((h.level, lang.clone()));
+                    }
+                    flow.set_block_hyphenator(block_hyphenator(
+                        docstyle,
+                        &containers,
+                        &section_langs,
+                        hyphenators,
+                    ));
+
+                    if h.level == 1 && docstyle.open_right {
+                        // Inserted pages get no header and footer in the next pass
+                        needs_layout = true;
+                        flow.push_spanning(&mut doc, ChapterBreak::new(&tracker));
+                    }
                     stylestack.push_style(|s| {
                         let font_size = docstyle.get_header_size(h.level);
                         s.set_font_size(font_size);
                         s.set_bold();
                     });
-                    stylestack.push_paragraph(Paragraph::default());
+                    stylestack.push_paragraph(TextBlock::new(&tracker));
+
+                    if let Some(number) = &headings[heading_index].number {
+                        let style = stylestack.get_style();
+                        stylestack.push_text(StyledString::new(format!("{} ", number), style));
+                    }
                 }
                 (Start, NodeValue::Text(t)) => {
                     let t = String::from_utf8_lossy(t);
                     let style = stylestack.get_style();
-                    stylestack.get_paragraph_mut().push_styled(t, style);
+
+                    for span in language::split_lang_spans(&t) {
+                        let text = match span {
+                            SpanPart::Start(lang) => {
+                                stylestack.push_lang(hyphenators.get(lang));
+                                continue;
+                            }
+                            SpanPart::End => {
+                                stylestack.pop_lang();
+                                continue;
+                            }
+                            SpanPart::Text(text) => text,
+                        };
+
+                        for part in equations::split_references(text) {
+                            match part {
+                                TextPart::Text(text) => {
+                                    for (run, style) in glyph_fallback.split(text, style) {
+                                        stylestack.push_text(StyledString::new(run, style));
+                                    }
+                                }
+                                TextPart::Reference(label) => {
+                                    let equation = equations
+                                        .iter()
+                                        .find(|eq| eq.label.as_deref() == Some(label));
+                                    let mut style = style;
+                                    if let Some(color) = docstyle.link_color {
+                                        style.set_color(color);
+                                    }
+                                    match equation.and_then(Equation::reference_text) {
+                                        Some(text) => {
+                                            let anchor = Equation::anchor(label);
+                                            stylestack.push_link(LinkTarget::Anchor(anchor));
+                                            stylestack.push_text(StyledString::new(text, style));
+                                            stylestack.pop_link();
+                                        }
+                                        None => {
+                                            if first_pass {
+                                                eprintln!(
+                                                    "Error: Reference in line ({}) to the unknown equation '{}'",
+                                                    ast_node.borrow().start_line,
+                                                    label
+                                                );
+                                            }
+                                            stylestack.push_text(StyledString::new("(??)", style));
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
                 }
                 (Start, NodeValue::Emph) => {
                     stylestack.push_style(|s| {
@@ -200,8 +896,154 @@ fn main() {
                         s.set_strikethrough();
                     });
                 }
-                (Start, NodeValue::List(_lst)) => {
-                    stylestack.push_list(UnorderedList::new());
+                (Start, NodeValue::Code(code)) => {
+                    let code = String::from_utf8_lossy(&code.literal);
+                    let style = stylestack.get_style();
+
+                    // Inline math is replaced with code spans before parsing
+                    if let Some(math) = InlineMath::from_placeholder(&code) {
+                        match math.convert() {
+                            Ok(runs) => {
+                                let runs = inline_math::styled_runs(
+                                    runs,
+                                    style,
+                                    math_font_family,
+                                    monospace_font,
+                                );
+                                for s in runs {
+                                    stylestack.push_text(s);
+                                }
+                            }
+                            Err(e) => {
+                                if first_pass {
+                                    eprintln!(
+                                        "Error while parsing inline math in line ({}:{}): {}",
+                                        math.line,
+                                        math.column,
+                                        e
+                                    );
+                                    eprintln!("    occured in '${}$'", math.source);
+                                }
+                                stylestack.push_text(StyledString::new(math.source, style));
+                            }
+                        }
+                        continue;
+                    }
+
+                    let mut style = style;
+                    style.set_font_family(monospace_font);
+                    stylestack.set_background(docstyle.inline_code_color);
+                    for (run, style) in glyph_fallback.split_monospace(&code, style) {
+                        stylestack.push_text(StyledString::new(run, style));
+                    }
+                    stylestack.set_background(None);
+                }
+                (Start, NodeValue::Link(link)) => {
+                    let url = String::from_utf8_lossy(&link.url).to_string();
+                    stylestack.push_style(|s| {
+                        if let Some(color) = docstyle.link_color {
+                            s.set_color(color);
+                        }
+                    });
+
+                    // Links without text show the number of the referenced section or equation
+                    let mut text = None;
+                    let target = match url.strip_prefix('#') {
+                        Some(anchor) => {
+                            // Headings take precedence over equations with the same label
+                            let mut anchor = anchor.to_string();
+                            match headings.iter().find(|h| h.anchor == anchor) {
+                                Some(heading) => {
+                                    text = Some(
+                                        heading
+                                            .number
+                                            .clone()
+                                            .unwrap_or_else(|| heading.text.clone()),
+                                    );
+                                }
+                                None => {
+                                    let equation = equations
+                                        .iter()
+                                        .find(|eq| eq.label.as_deref() == Some(anchor.as_str()))
+                                        .and_then(Equation::reference_text);
+                                    match equation {
+                                        Some(number) => {
+                                            anchor = Equation::anchor(&anchor);
+                                            text = Some(number);
+                                        }
+                                        None if first_pass => eprintln!(
+                                            "Error: Link in line ({}) references the unknown anchor '{}'",
+                                            ast_node.borrow().start_line,
+                                            url
+                                        ),
+                                        None => (),
+                                    }
+                                }
+                            }
+                            LinkTarget::Anchor(anchor)
+                        }
+                        None => LinkTarget::Uri(url),
+                    };
+                    stylestack.push_link(target);
+
+                    if let (Some(text), None) = (text, arena_node.first_child()) {
+                        let style = stylestack.get_style();
+                        stylestack.push_text(StyledString::new(text, style));
+                    }
+                }
+                (Start, NodeValue::List(lst)) => {
+                    let list = match lst.list_type {
+                        ListType::Bullet => ListBuilder::unordered(),
+                        ListType::Ordered => {
+                            let numbering = docstyle.get_list_numbering(stylestack.ordered_list_depth());
+                            ListBuilder::ordered(lst.start, lst.delimiter, numbering)
+                        }
+                    };
+                    stylestack.push_list(list);
+                }
+                (Start, NodeValue::Item(_)) if stylestack.in_footnote() => {
+                    // Lists in footnotes are printed as paragraphs that start with the bullet
+                    stylestack.footnote_break();
+                    let bullet = stylestack.get_list_mut().next_bullet();
+                    let style = stylestack.get_style();
+                    stylestack.push_text(StyledString::new(format!("{} ", bullet), style));
+                }
+                (Start, NodeValue::Item(_item)) => {
+                    stylestack.get_list_mut().start_item();
+                }
+                (Start, NodeValue::TaskItem { checked, .. }) if stylestack.in_footnote() => {
+                    stylestack.footnote_break();
+                    let list = stylestack.get_list_mut();
+                    let number = match list.is_ordered() {
+                        true => format!("{} ", list.next_bullet()),
+                        false => String::new(),
+                    };
+                    let checkbox = if *checked { "[x] " } else { "[ ] " };
+                    let style = stylestack.get_style();
+                    stylestack.push_text(StyledString::new(number + checkbox, style));
+                }
+                (Start, NodeValue::TaskItem { checked, .. }) => {
+                    stylestack.get_list_mut().start_task_item(*checked);
+                }
+                (Start, NodeValue::Table(_)) if stylestack.in_footnote() => {
+                    // Tables in footnotes are printed as one paragraph per row
+                }
+                (Start, NodeValue::Table(alignments)) => {
+                    stylestack.table = Some(TableBuilder::new(alignments.clone()));
+                }
+                (Start, NodeValue::TableRow(header)) => {
+                    if *header {
+                        stylestack.push_style(|s| {
+                            s.set_bold();
+                        });
+                    }
+                }
+                (Start, NodeValue::TableCell) if stylestack.in_footnote() => {}
+                (Start, NodeValue::TableCell) => {
+                    // Table cells directly contain the inline elements, so the text is collected
+                    // in the cell until it ends
+                    let alignment = stylestack.get_table_mut().next_cell_alignment();
+                    stylestack.table_cell = Some(TableCell::new(alignment));
                 }
                 (Start, NodeValue::BlockQuote) => {
                     stylestack.push_style(|s| {
@@ -210,6 +1052,23 @@ fn main() {
                     });
                     stylestack.blockquote_active = true;
                 }
+                (Start, NodeValue::Image(node_img)) if stylestack.in_footnote() => {
+                    if first_pass {
+                        eprintln!(
+                            "Images are not supported in footnotes, the image '{}' is left out",
+                            String::from_utf8_lossy(&node_img.url)
+                        );
+                    }
+                }
+                (Start, NodeValue::Image(node_img)) if stylestack.table_cell.is_some() => {
+                    // The alt text of the image is still printed in the cell
+                    if first_pass {
+                        eprintln!(
+                            "Images are not supported in table cells, the image '{}' is left out",
+                            String::from_utf8_lossy(&node_img.url)
+                        );
+                    }
+                }
                 (Start, NodeValue::Image(node_img)) => {
                     let path = String::from_utf8_lossy(&node_img.url);
 
@@ -230,37 +1089,41 @@ fn main() {
                                 match key.trim() {
                                     "scale" => match value.trim().parse() {
                                         Ok(value) => {
-                                            println!("Scale value {}", value);
                                             scale_x = value;
                                             scale_y = value;
                                         }
-                                        Err(_) => eprintln!("Failed to parse '{}' as scale value", value),
+                                        Err(_) if first_pass => eprintln!("Failed to parse '{}' as scale value", value),
+                                        Err(_) => (),
                                     }
                                     "scale-x" => match value.trim().parse() {
                                         Ok(value) => {
                                             scale_x = value;
                                         }
-                                        Err(_) => eprintln!("Failed to parse '{}' as scale value", value),
+                                        Err(_) if first_pass => eprintln!("Failed to parse '{}' as scale value", value),
+                                        Err(_) => (),
                                     }
                                     "scale-y" => match value.trim().parse() {
                                         Ok(value) => {
                                             scale_y = value;
                                         }
-                                        Err(_) => eprintln!("Failed to parse '{}' as scale value", value),
+                                        Err(_) if first_pass => eprintln!("Failed to parse '{}' as scale value", value),
+                                        Err(_) => (),
                                     }
                                     "rotate" => match value.trim().parse() {
                                         Ok(value) => rotation = value,
-                                        Err(_) => eprintln!("Failed to parse '{}' as rotate value", value),
+                                        Err(_) if first_pass => eprintln!("Failed to parse '{}' as rotate value", value),
+                                        Err(_) => (),
                                     }
                                     _ => ()
                                 }
                             }
-                            _ => {
+                            _ if first_pass => {
                                 eprintln!(
                                     "Failed to parse key value props from image title: '{}'", 
                                     title
                                 );
                             }
+                            _ => (),
                         }
                     }
 
@@ -270,86 +1133,121 @@ fn main() {
                             img.set_alignment(Alignment::Center);
                             img.set_clockwise_rotation(rotation);
                             img.set_fit_width(true);
-                            doc.push(PaddedElement::new(
+                            flow.push_spanning(&mut doc, PaddedElement::new(
                                 img, 
                                 Margins::trbl(0, 0, docstyle.paragraph_spacing, 0)
                             ));
                         }
-                        _ => {
+                        _ if first_pass => {
                             eprintln!("Error loading image: {}", String::from_utf8_lossy(&node_img.url));
                         }
+                        _ => (),
                     }
                 }
+                (Start, NodeValue::LineBreak) if stylestack.in_footnote() => {
+                    stylestack.footnote_break();
+                }
                 (Start, NodeValue::LineBreak) => {
-                    doc.push(PaddedElement::new(
-                        stylestack.pop_paragraph(),
+                    let p = stylestack.pop_paragraph();
+                    let (p, _) = stylestack.track_element(&tracker, p);
+                    flow.push(&mut doc, PaddedElement::new(
+                        p,
                         Margins::trbl(0, 0, docstyle.paragraph_spacing, 0),
                     ));
 
-                    let mut p = Paragraph::default();
+                    let mut p = TextBlock::new(&tracker);
                     if docstyle.align_justify {
                         p.set_alignment(Alignment::Justified(true));
                     }
                     stylestack.push_paragraph(p);
                 }
                 (Start, NodeValue::SoftBreak) => {
+                    stylestack.push_space();
+                }
+                (Start, NodeValue::FootnoteReference(name)) => {
+                    let number = footnotes.reference(&String::from_utf8_lossy(name));
                     let style = stylestack.get_style();
-                    stylestack.get_paragraph_mut().push_if_text(
-                        StyledString::new(' ', style), 
-                        |text| {
-                            text.last().map(|w| !w.s.ends_with(' ')).unwrap_or(true)
-                        }
-                    );
+                    stylestack.push_text(footnotes.marker(number, style));
+                    // The footnote is printed on the page of the element with the reference
+                    stylestack.push_anchor(Footnotes::anchor(number));
+                    needs_layout = true;
                 }
+                (Start, NodeValue::FootnoteDefinition(_)) => {
+                    stylestack.start_footnote(docstyle.footnote_size);
+                }
+                (Start, NodeValue::ThematicBreak) if stylestack.in_footnote() => {}
                 (Start, NodeValue::ThematicBreak) => {
-                    doc.push(PageBreak::new());
+                    flow.push_spanning(&mut doc, PageBreak::new());
+                }
+                (Start, NodeValue::CodeBlock(cb)) if stylestack.in_footnote() => {
+                    // Code and math blocks in footnotes are printed as one paragraph per line
+                    let language = String::from_utf8_lossy(&cb.info);
+                    if equations::math_block_options(&language, false).is_some() {
+                        if first_pass {
+                            eprintln!(
+                                "Math blocks are not supported in footnotes, the block in line ({}) \
+                                is printed as source",
+                                ast_node.borrow().start_line
+                            );
+                        }
+                        let math_str = String::from_utf8_lossy(&cb.literal);
+                        equation_index += equations::split_equations(&math_str).len();
+                    }
+                    let style = stylestack.get_style().with_font_family(monospace_font);
+                    stylestack.footnote_break();
+                    for line in String::from_utf8_lossy(&cb.literal).lines() {
+                        stylestack.push_text(StyledString::new(line, style));
+                        stylestack.footnote_break();
+                    }
                 }
                 (Start, NodeValue::CodeBlock(cb)) => {
                     let language = String::from_utf8_lossy(&cb.info);
 
-                    match language.as_ref() {
-                        "math" | "latex math" => {
+                    match equations::math_block_options(&language, docstyle.equation_numbering) {
+                        Some(_) => {
                             let math_str = String::from_utf8_lossy(&cb.literal);
-                            let lines = math_str.lines();
-                            let mut math_lines: Vec<String> = Vec::new();
-    
-                            // Lines separated by a fully empty line will be rendered vertically stacked
-                            let mut append_to_prev = false;
-                            for line in lines {
-                                if line.trim().is_empty() {
-                                    append_to_prev = false;
-                                } else {
-                                    if append_to_prev {
-                                        math_lines.last_mut().unwrap().push_str(line);
-                                    } else {
-                                        math_lines.push(line.to_string());
-                                    }
-                                    append_to_prev = true;
-                                }
-                            }
-    
-                            for math in math_lines {
-                                let mut math_block = match Math::new(&math) {
+                            let count = equations::split_equations(&math_str).len();
+                            let block_equations = &equations[equation_index..equation_index + count];
+                            equation_index += count;
+
+                            for equation in block_equations {
+                                let math = &equation.source;
+                                let mut math_block = match Math::new(math) {
                                     Ok(it) => it,
                                     Err(e) => {
-                                        eprintln!(
-                                            "Error while parsing math block in line ({}): {}", 
-                                            ast_node.borrow().start_line,
-                                            e
-                                        );
-                                        eprintln!("    occured in '{}'", math);
+                                        if first_pass {
+                                            eprintln!(
+                                                "Error while parsing math block in line ({}): {}", 
+                                                ast_node.borrow().start_line,
+                                                e
+                                            );
+                                            eprintln!("    occured in '{}'", math);
+                                        }
                                         continue;
                                     }
                                 };
                                 math_block.set_alignment(Alignment::Center);
-    
-                                doc.push(PaddedElement::new(
-                                    math_block, 
-                                    Margins::trbl(0, 0, docstyle.paragraph_spacing, 0)
-                                ));
+                                let margins = Margins::trbl(0, 0, docstyle.paragraph_spacing, 0);
+
+                                match equation.reference_text() {
+                                    Some(number) => {
+                                        let (math_block, id) = tracker.track(
+                                            NumberedEquation::new(math_block, number),
+                                        );
+                                        if let Some(label) = &equation.label {
+                                            tracker.add_anchor(id, &Equation::anchor(label));
+                                        }
+                                        let math_block = PaddedElement::new(math_block, margins);
+                                        flow.push(&mut doc, math_block);
+                                    }
+                                    None => {
+                                        let math_block = PaddedElement::new(math_block, margins);
+                                        flow.push(&mut doc, math_block);
+                                    }
+                                }
                             }
                         }
-                        _ => {
+                        None => {
                             let code_str = String::from_utf8_lossy(&cb.literal);
 
                             let code_block = CodeBlock::new(
@@ -359,7 +1257,7 @@ fn main() {
                                 Style::new().with_font_family(monospace_font).with_font_size(10)
                             );
 
-                            doc.push(PaddedElement::new(
+                            flow.push(&mut doc, PaddedElement::new(
                                 code_block.padded(Margins::all(1))
                                     .framed(
                                         LineStyle::new()
@@ -376,13 +1274,86 @@ fn main() {
                 (End, NodeValue::Paragraph) => {
                     let new_elem = stylestack.pop_paragraph();
 
+                    if stylestack.in_footnote() {
+                        // The text was collected in the footnote. References in footnotes can't
+                        // be located.
+                        stylestack.footnote_break();
+                        stylestack.pending_anchors.clear();
+                        continue;
+                    }
+
+                    // Paragraphs that only consist of a marker are not printed
+                    let marker = node_text(arena_node);
+                    let marker = marker.trim();
+                    if marker == toc::TOC_MARKER {
+                        needs_layout = true;
+                        flow.push_spanning(&mut doc, PaddedElement::new(
+                            toc::make_toc(docstyle, headings, layout, &tracker, &glyph_fallback),
+                            Margins::trbl(0, 0, docstyle.paragraph_spacing * 2.0, 0),
+                        ));
+                        continue;
+                    }
+                    if marker == landscape::LANDSCAPE_START
+                        && !containers.contains(&Container::Landscape)
+                    {
+                        containers.push(Container::Landscape);
+                        // Breaks at the top of a page are applied in the next pass
+                        needs_layout = true;
+                        flow.push_spanning(&mut doc, OrientationBreak::new(true, &tracker));
+                        continue;
+                    }
+                    if let Some(count) = columns::parse_columns_marker(marker) {
+                        // The section is still started with an invalid count, so that it's
+                        // closed by its end marker
+                        let count = count.unwrap_or_else(|e| {
+                            if first_pass {
+                                eprintln!(
+                                    "Error in line ({}): {}, the section is set in two columns",
+                                    ast_node.borrow().start_line,
+                                    e
+                                );
+                            }
+                            2
+                        });
+                        containers.push(Container::Columns);
+                        flow.start_section(&mut doc, count);
+                        continue;
+                    }
+                    if let Some(lang) = language::parse_lang_marker(marker) {
+                        containers.push(Container::Lang(lang));
+                        flow.set_block_hyphenator(block_hyphenator(
+                            docstyle,
+                            &containers,
+                            &section_langs,
+                            hyphenators,
+                        ));
+                        continue;
+                    }
+                    if marker == CONTAINER_END && !containers.is_empty() {
+                        match containers.pop().unwrap() {
+                            Container::Landscape => flow.push_spanning(
+                                &mut doc,
+                                OrientationBreak::new(false, &tracker),
+                            ),
+                            Container::Columns => flow.end_section(&mut doc),
+                            Container::Lang(_) => flow.set_block_hyphenator(block_hyphenator(
+                                docstyle,
+                                &containers,
+                                &section_langs,
+                                hyphenators,
+                            )),
+                        }
+                        continue;
+                    }
+                    let (new_elem, _) = stylestack.track_element(&tracker, new_elem);
+
                     match stylestack.has_list() {
                         true => stylestack.get_list_mut().push(new_elem),
                         false => {
                             if stylestack.blockquote_active {
                                 // TODO: Do something to better mark block quotes
                             }
-                            doc.push(PaddedElement::new(
+                            flow.push(&mut doc, PaddedElement::new(
                                 new_elem,
                                 Margins::trbl(0, 0, docstyle.paragraph_spacing, 0),
                             ));
@@ -390,8 +1361,14 @@ fn main() {
                     }
                 }
                 (End, NodeValue::Heading(_)) => {
-                    doc.push(PaddedElement::new(
-                        stylestack.pop_paragraph(),
+                    let heading = &headings[heading_index];
+                    heading_index += 1;
+
+                    let p = stylestack.pop_paragraph();
+                    let (p, id) = stylestack.track_element(&tracker, p);
+                    tracker.add_anchor(id, &heading.anchor);
+                    flow.push_spanning(&mut doc, PaddedElement::new(
+                        p,
                         Margins::trbl(docstyle.header_spacing, 0, docstyle.header_spacing, 0),
                     ));
                     stylestack.pop_style();
@@ -399,19 +1376,84 @@ fn main() {
                 (End, NodeValue::Emph | NodeValue::Strong | NodeValue::Strikethrough) => {
                     stylestack.pop_style();
                 }
+                (End, NodeValue::Link(link)) => {
+                    stylestack.pop_style();
+                    stylestack.pop_link();
+
+                    let url = String::from_utf8_lossy(&link.url);
+                    let text = node_text(arena_node);
+                    // Autolinks already show the URL (without the mailto: prefix for emails)
+                    let shows_url = text == url || format!("mailto:{}", text) == url;
+                    let is_internal = url.starts_with('#');
+                    if docstyle.print_link_urls && !shows_url && !is_internal {
+                        let style = stylestack.get_style();
+                        if stylestack.in_footnote() {
+                            // Footnotes can't have footnotes, so the URL is printed inline
+                            stylestack.push_text(StyledString::new(format!(" ({})", url), style));
+                        } else {
+                            let note_style = Style::new().with_font_size(docstyle.footnote_size);
+                            let note = vec![StyledString::new(url, note_style)];
+                            let number = footnotes.add_note(note);
+                            stylestack.push_text(footnotes.marker(number, style));
+                            stylestack.push_anchor(Footnotes::anchor(number));
+                            needs_layout = true;
+                        }
+                    }
+                }
+                (End, NodeValue::TableCell) if stylestack.in_footnote() => {
+                    let style = stylestack.get_style();
+                    stylestack.push_text(StyledString::new("\u{2003}", style));
+                }
+                (End, NodeValue::TableCell) => {
+                    let cell = stylestack.table_cell.take().unwrap();
+                    stylestack.get_table_mut().push_cell(cell);
+                }
+                (End, NodeValue::TableRow(header)) => {
+                    if *header {
+                        stylestack.pop_style();
+                    }
+                    match stylestack.in_footnote() {
+                        true => stylestack.footnote_break(),
+                        false => stylestack.get_table_mut().finish_row(*header),
+                    }
+                }
+                (End, NodeValue::Table(_)) if stylestack.in_footnote() => {}
+                (End, NodeValue::Table(_)) => {
+                    let table = stylestack.table.take().unwrap();
+                    let mut table = table.build(docstyle.table_rules, &tracker);
+                    table.set_continued_label(docstyle.table_continued_label.clone());
+                    let (table, _) = stylestack.track_element(&tracker, table);
+                    let table = PaddedElement::new(
+                        table,
+                        Margins::trbl(0, 0, docstyle.paragraph_spacing * 2.0, 0),
+                    );
+
+                    match stylestack.has_list() {
+                        true => stylestack.get_list_mut().push(table),
+                        false => flow.push(&mut doc, table),
+                    }
+                }
+                (End, NodeValue::FootnoteDefinition(name)) => {
+                    let text = stylestack.finish_footnote();
+                    footnotes.set_text(&String::from_utf8_lossy(name), text);
+                }
                 (End, NodeValue::BlockQuote) => {
                     stylestack.pop_style();
                     stylestack.blockquote_active = false;
                 }
                 (End, NodeValue::List(_lst)) => {
                     let list = stylestack.pop_list();
+                    if stylestack.in_footnote() {
+                        stylestack.footnote_break();
+                        continue;
+                    }
 
                     match stylestack.has_list() {
                         true => {
-                            stylestack.get_list_mut().push_no_bullet(list);
+                            stylestack.get_list_mut().push(list.layout);
                         }
-                        false => doc.push(PaddedElement::new(
-                            list, 
+                        false => flow.push(&mut doc, PaddedElement::new(
+                            list.layout, 
                             Margins::trbl(0, 0, docstyle.paragraph_spacing, 0)
                         )),
                     }
@@ -429,13 +1471,13 @@ fn main() {
                     // Text is inserted at Start(Text), and commited when the paragraph ends. So
                     // Nothing to do here
                 }
-                (_, NodeValue::Item(_item)) => {
-                    // Items automatically contain a paragraph, so don't do anything here
+                (End, NodeValue::Item(_item)) => {
+                    // Items automatically contain a paragraph, which is pushed to the list when it
+                    // ends. So nothing to do here
                 }
 
                 _ => ()
                 // NodeValue::Document => todo!(),
-                // NodeValue::FrontMatter(_) => todo!(),
                 // NodeValue::DescriptionList => todo!(),
                 // NodeValue::DescriptionItem(_) => todo!(),
                 // NodeValue::DescriptionTerm => todo!(),
@@ -443,19 +1485,12 @@ fn main() {
                 // NodeValue::CodeBlock(_) => todo!(),
                 // NodeValue::HtmlBlock(_) => todo!(),
                 // NodeValue::ThematicBreak => todo!(),
-                // NodeValue::FootnoteDefinition(_) => todo!(),
-                // NodeValue::Table(_) => todo!(),
-                // NodeValue::TableRow(_) => todo!(),
-                // NodeValue::TableCell => todo!(),
-                // NodeValue::TaskItem { checked, symbol } => todo!(),
-                // NodeValue::Code(_) => todo!(),
                 // NodeValue::HtmlInline(_) => todo!(),
                 // NodeValue::Strikethrough => todo!(),
                 // NodeValue::Superscript => todo!(),
-                // NodeValue::Link(_) => todo!(),
-                // NodeValue::FootnoteReference(_) => todo!(),
         }
     }
+    flow.finish(&mut doc);
 
-    doc.render_to_file(&cli_args.output).unwrap();
+    (doc, tracker, footnotes, glyph_fallback, needs_layout)
 }
@@ -0,0 +1,145 @@
+/// The different styles that can be used to render the number of an enumerated element, for
+/// example the items of an ordered list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberingStyle {
+    /// 1, 2, 3, ...
+    Decimal,
+    /// a, b, c, ..., z, aa, ab, ...
+    LowerAlpha,
+    /// A, B, C, ..., Z, AA, AB, ...
+    UpperAlpha,
+    /// i, ii, iii, iv, ...
+    LowerRoman,
+    /// I, II, III, IV, ...
+    UpperRoman,
+}
+
+impl NumberingStyle {
+    pub fn format(&self, number: usize) -> String {
+        match self {
+            NumberingStyle::Decimal => number.to_string(),
+            NumberingStyle::LowerAlpha => to_alpha(number),
+            NumberingStyle::UpperAlpha => to_alpha(number).to_uppercase(),
+            NumberingStyle::LowerRoman => to_roman(number),
+            NumberingStyle::UpperRoman => to_roman(number).to_uppercase(),
+        }
+    }
+}
+
+/// Bijective base-26 representation, so that 26 => z and 27 => aa. Zero can't be represented and
+/// falls back to the decimal representation
+fn to_alpha(mut number: usize) -> String {
+    if number == 0 {
+        return number.to_string();
+    }
+
+    let mut letters = Vec::new();
+    while number > 0 {
+        number -= 1;
+        letters.push((b'a' + (number % 26) as u8) as char);
+        number /= 26;
+    }
+
+    letters.iter().rev().collect()
+}
+
+/// Lowercase roman numerals. Numbers that can't be represented as roman numerals (0 and numbers
+/// of 4000 and above) fall back to the decimal representation
+fn to_roman(mut number: usize) -> String {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+
+    if number == 0 || number >= 4000 {
+        return number.to_string();
+    }
+
+    let mut roman = String::new();
+    for (value, numeral) in NUMERALS {
+        while number >= value {
+            roman.push_str(numeral);
+            number -= value;
+        }
+    }
+
+    roman
+}
+
+/// Convert the digits of the text to unicode superscript characters, used for footnote markers.
+/// Characters without superscript variant are kept as they are.
+pub fn to_superscript(text: &str) -> String {
+    text.chars().map(|c| superscript_char(c).unwrap_or(c)).collect()
+}
+
+/// The unicode superscript variant of a digit or minus sign
+pub fn superscript_char(c: char) -> Option<char> {
+    let superscript = match c {
+        '0' => '⁰',
+        '1' => '¹',
+        '2' => '²',
+        '3' => '³',
+        '4' => '⁴',
+        '5' => '⁵',
+        '6' => '⁶',
+        '7' => '⁷',
+        '8' => '⁸',
+        '9' => '⁹',
+        '-' | '−' => '⁻',
+        _ => return None,
+    };
+
+    Some(superscript)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alpha() {
+        assert_eq!(to_alpha(1), "a");
+        assert_eq!(to_alpha(26), "z");
+        assert_eq!(to_alpha(27), "aa");
+        assert_eq!(to_alpha(52), "az");
+        assert_eq!(to_alpha(703), "aaa");
+        assert_eq!(to_alpha(0), "0");
+    }
+
+    #[test]
+    fn roman() {
+        assert_eq!(to_roman(1), "i");
+        assert_eq!(to_roman(4), "iv");
+        assert_eq!(to_roman(9), "ix");
+        assert_eq!(to_roman(14), "xiv");
+        assert_eq!(to_roman(1994), "mcmxciv");
+        assert_eq!(to_roman(3999), "mmmcmxcix");
+        assert_eq!(to_roman(0), "0");
+        assert_eq!(to_roman(4000), "4000");
+    }
+
+    #[test]
+    fn uppercase_styles() {
+        assert_eq!(NumberingStyle::UpperAlpha.format(28), "AB");
+        assert_eq!(NumberingStyle::UpperRoman.format(12), "XII");
+    }
+
+    #[test]
+    fn superscript() {
+        assert_eq!(to_superscript("12"), "¹²");
+        assert_eq!(to_superscript("-3"), "⁻³");
+        assert_eq!(to_superscript("1a"), "¹a");
+        assert_eq!(superscript_char('x'), None);
+    }
+}
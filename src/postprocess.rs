@@ -0,0 +1,395 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use genpdf::{Mm, Size};
+use lopdf::{
+    content::{Content, Operation},
+    dictionary, Object, ObjectId, Stream, StringFormat,
+};
+
+use crate::{
+    base_style::DocumentStyle,
+    headings::Heading,
+    tracking::{LinkTarget, PageRect, RenderTracker},
+};
+
+/// Conversion factor from mm to PDF user space units (points)
+const MM_TO_PT: f64 = 72.0 / 25.4;
+
+/// Add the features that genpdf can't produce by itself to the rendered PDF. The information
+/// about where the elements ended up is taken from the tracker that was used during rendering.
+pub fn postprocess(
+    pdf: &[u8],
+    tracker: &RenderTracker,
+    headings: &[Heading],
+    style: &DocumentStyle,
+) -> anyhow::Result<Vec<u8>> {
+    let mut doc = lopdf::Document::load_mem(pdf).context("Failed to load the rendered PDF")?;
+    let pages = doc.get_pages();
+    let portrait_size = style.page_size();
+    let rotated_pages = tracker.rotated_pages();
+    let page_size = |page: usize| match rotated_pages.contains(&page) {
+        true => Size::new(portrait_size.height, portrait_size.width),
+        false => portrait_size,
+    };
+
+    // The areas are in the coordinates of the portrait pages, so the text is marked before the
+    // pages are rotated
+    mark_languages(&mut doc, &pages, &tracker.lang_areas(), portrait_size.height)?;
+
+    for page in &rotated_pages {
+        if let Some(page_id) = pages.get(&(*page as u32)) {
+            rotate_page(&mut doc, *page_id, portrait_size)?;
+        }
+    }
+
+    for (areas, target) in tracker.links() {
+        for rect in areas {
+            let page_id = match pages.get(&(rect.page as u32)) {
+                Some(id) => *id,
+                None => continue,
+            };
+
+            let action = match &target {
+                LinkTarget::Uri(uri) => dictionary! {
+                    "S" => "URI",
+                    "URI" => Object::string_literal(uri.as_str()),
+                },
+                LinkTarget::Anchor(name) => {
+                    let dest = match tracker.anchor_area(name) {
+                        Some(dest) => dest,
+                        None => continue,
+                    };
+                    let dest_page = match pages.get(&(dest.page as u32)) {
+                        Some(id) => *id,
+                        None => continue,
+                    };
+                    dictionary! {
+                        "S" => "GoTo",
+                        "D" => destination(dest_page, &dest, page_size(dest.page)),
+                    }
+                }
+            };
+
+            let annotation = doc.add_object(dictionary! {
+                "Type" => "Annot",
+                "Subtype" => "Link",
+                "Rect" => pdf_rect(&rect, page_size(rect.page)),
+                "Border" => vec![Object::Integer(0); 3],
+                "A" => action,
+            });
+            add_annotation(&mut doc, page_id, annotation)?;
+        }
+    }
+
+    if let Some(lang) = &style.lang {
+        doc.catalog_mut()?.set("Lang", Object::string_literal(lang.as_str()));
+    }
+
+    if style.outline_depth > 0 {
+        add_outline(&mut doc, &pages, tracker, headings, style.outline_depth, &page_size)?;
+    }
+
+    let mut out = Vec::new();
+    doc.save_to(&mut out).context("Failed to write the PDF")?;
+    Ok(out)
+}
+
+struct OutlineItem {
+    id: ObjectId,
+    title: String,
+    dest: Vec<Object>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+/// Add the document outline (bookmarks) with all headings up to the given level
+fn add_outline(
+    doc: &mut lopdf::Document,
+    pages: &BTreeMap<u32, ObjectId>,
+    tracker: &RenderTracker,
+    headings: &[Heading],
+    max_level: u8,
+    page_size: &dyn Fn(usize) -> Size,
+) -> anyhow::Result<()> {
+    let mut items: Vec<OutlineItem> = Vec::new();
+    let mut roots = Vec::new();
+    // Indices of the items that new items could be nested in, with their heading level
+    let mut open: Vec<(u8, usize)> = Vec::new();
+
+    for heading in headings.iter().filter(|h| h.level <= max_level) {
+        let area = match tracker.anchor_area(&heading.anchor) {
+            Some(area) => area,
+            None => continue,
+        };
+        let page_id = match pages.get(&(area.page as u32)) {
+            Some(id) => *id,
+            None => continue,
+        };
+
+        while matches!(open.last(), Some((level, _)) if *level >= heading.level) {
+            open.pop();
+        }
+
+        let index = items.len();
+        let parent = open.last().map(|(_, parent)| *parent);
+        match parent {
+            Some(parent) => items[parent].children.push(index),
+            None => roots.push(index),
+        }
+        items.push(OutlineItem {
+            id: doc.new_object_id(),
+            title: heading.numbered_text(),
+            dest: destination(page_id, &area, page_size(area.page)),
+            parent,
+            children: Vec::new(),
+        });
+        open.push((heading.level, index));
+    }
+
+    if items.is_empty() {
+        return Ok(());
+    }
+
+    let outlines_id = doc.new_object_id();
+
+    for index in 0..items.len() {
+        let item = &items[index];
+        let siblings = match item.parent {
+            Some(parent) => &items[parent].children,
+            None => &roots,
+        };
+        let position = siblings.iter().position(|it| *it == index).unwrap();
+
+        let mut dict = dictionary! {
+            "Title" => text_string(&item.title),
+            "Parent" => item.parent.map(|it| items[it].id).unwrap_or(outlines_id),
+            "Dest" => item.dest.clone(),
+        };
+        if position > 0 {
+            dict.set("Prev", items[siblings[position - 1]].id);
+        }
+        if let Some(next) = siblings.get(position + 1) {
+            dict.set("Next", items[*next].id);
+        }
+        if let (Some(first), Some(last)) = (item.children.first(), item.children.last()) {
+            dict.set("First", items[*first].id);
+            dict.set("Last", items[*last].id);
+            dict.set("Count", count_descendants(&items, index) as i64);
+        }
+
+        doc.objects.insert(item.id, dict.into());
+    }
+
+    doc.objects.insert(
+        outlines_id,
+        dictionary! {
+            "Type" => "Outlines",
+            "First" => items[roots[0]].id,
+            "Last" => items[*roots.last().unwrap()].id,
+            "Count" => items.len() as i64,
+        }
+        .into(),
+    );
+
+    let catalog = doc.catalog_mut()?;
+    catalog.set("Outlines", outlines_id);
+    catalog.set("PageMode", "UseOutlines");
+
+    Ok(())
+}
+
+/// Turn the portrait page into a landscape page. genpdf placed the content relative to the top
+/// left corner of the portrait page, so the content is moved down to the top of the landscape
+/// page, which is lower in PDF coordinates.
+fn rotate_page(
+    doc: &mut lopdf::Document,
+    page_id: ObjectId,
+    portrait_size: Size,
+) -> anyhow::Result<()> {
+    let width = portrait_size.height.0 * MM_TO_PT;
+    let height = portrait_size.width.0 * MM_TO_PT;
+    let offset = height - portrait_size.height.0 * MM_TO_PT;
+
+    let transform = format!("1 0 0 1 0 {:.3} cm\n", offset);
+    let transform = doc.add_object(Stream::new(dictionary! {}, transform.into_bytes()));
+
+    let page = doc.get_dictionary_mut(page_id)?;
+    // The contents can either be a single stream or an array of streams
+    let mut contents = vec![Object::Reference(transform)];
+    match page.get(b"Contents") {
+        Ok(Object::Array(streams)) => contents.extend(streams.iter().cloned()),
+        Ok(stream) => contents.push(stream.clone()),
+        Err(_) => (),
+    }
+    page.set("Contents", contents);
+    page.set(
+        "MediaBox",
+        vec![
+            Object::Integer(0),
+            Object::Integer(0),
+            Object::Real(width as f32),
+            Object::Real(height as f32),
+        ],
+    );
+    page.remove(b"CropBox");
+
+    Ok(())
+}
+
+/// Store the language of sections, blocks and spans that are not in the document language.
+/// Every text object (`BT` … `ET`) that starts in a language area is wrapped in a marked content
+/// sequence with the language (`/Span <</Lang (de)>> BDC` … `EMC`). If areas overlap, like a
+/// span in a block, the smallest one is used.
+fn mark_languages(
+    doc: &mut lopdf::Document,
+    pages: &BTreeMap<u32, ObjectId>,
+    areas: &[(PageRect, String)],
+    page_height: Mm,
+) -> anyhow::Result<()> {
+    for (page, page_id) in pages {
+        let page_areas: Vec<_> = areas
+            .iter()
+            .filter(|(rect, _)| rect.page as u32 == *page)
+            .collect();
+        if page_areas.is_empty() {
+            continue;
+        }
+
+        let content = doc.get_page_content(*page_id)?;
+        let content = Content::decode(&content)?;
+        let mut operations = Vec::with_capacity(content.operations.len());
+        let mut text_object: Option<Vec<Operation>> = None;
+        let mut changed = false;
+
+        for operation in content.operations {
+            match (operation.operator.as_str(), text_object.as_mut()) {
+                ("BT", None) => text_object = Some(vec![operation]),
+                ("ET", Some(ops)) => {
+                    ops.push(operation);
+                    let ops = text_object.take().unwrap();
+                    let lang = text_position(&ops)
+                        .map(|(x, y)| (Mm(x / MM_TO_PT), page_height - Mm(y / MM_TO_PT)))
+                        .and_then(|(x, y)| smallest_area(&page_areas, x, y));
+                    match lang {
+                        Some(lang) => {
+                            changed = true;
+                            operations.push(Operation::new(
+                                "BDC",
+                                vec![
+                                    Object::Name(b"Span".to_vec()),
+                                    Object::Dictionary(dictionary! {
+                                        "Lang" => Object::string_literal(lang),
+                                    }),
+                                ],
+                            ));
+                            operations.extend(ops);
+                            operations.push(Operation::new("EMC", vec![]));
+                        }
+                        None => operations.extend(ops),
+                    }
+                }
+                (_, Some(ops)) => ops.push(operation),
+                (_, None) => operations.push(operation),
+            }
+        }
+        // An unterminated text object is kept as it is
+        operations.extend(text_object.into_iter().flatten());
+
+        if changed {
+            let content = Content { operations }.encode()?;
+            doc.change_page_content(*page_id, content)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The start of the text object in PDF coordinates, from its first `Td` or `Tm` operator
+fn text_position(operations: &[Operation]) -> Option<(f64, f64)> {
+    operations.iter().find_map(|op| {
+        let (x, y) = match op.operator.as_str() {
+            "Td" => (op.operands.first()?, op.operands.get(1)?),
+            "Tm" => (op.operands.get(4)?, op.operands.get(5)?),
+            _ => return None,
+        };
+        Some((x.as_float().ok()? as f64, y.as_float().ok()? as f64))
+    })
+}
+
+/// The language of the smallest area that contains the position
+fn smallest_area<'a>(areas: &[&'a (PageRect, String)], x: Mm, y: Mm) -> Option<&'a str> {
+    areas
+        .iter()
+        .filter(|(rect, _)| rect.left <= x && x <= rect.right && rect.top <= y && y <= rect.bottom)
+        .min_by(|(a, _), (b, _)| {
+            let size = |r: &PageRect| (r.right - r.left).0 * (r.bottom - r.top).0;
+            size(a).total_cmp(&size(b))
+        })
+        .map(|(_, lang)| lang.as_str())
+}
+
+fn count_descendants(items: &[OutlineItem], index: usize) -> usize {
+    items[index]
+        .children
+        .iter()
+        .map(|child| 1 + count_descendants(items, *child))
+        .sum()
+}
+
+/// Encode the text as PDF text string. UTF-16BE with byte order mark is used, so that all
+/// characters are supported
+fn text_string(text: &str) -> Object {
+    let mut bytes = vec![0xfe, 0xff];
+    for unit in text.encode_utf16() {
+        bytes.extend_from_slice(&unit.to_be_bytes());
+    }
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+/// Destination that shows the top left corner of the rect, keeping the current zoom level
+fn destination(page_id: ObjectId, rect: &PageRect, page_size: Size) -> Vec<Object> {
+    let (left, top) = pdf_point(rect.left, rect.top, page_size);
+    vec![page_id.into(), "XYZ".into(), left, top, Object::Null]
+}
+
+/// Convert the rect to a PDF rectangle array
+fn pdf_rect(rect: &PageRect, page_size: Size) -> Vec<Object> {
+    let (left, bottom) = pdf_point(rect.left, rect.bottom, page_size);
+    let (right, top) = pdf_point(rect.right, rect.top, page_size);
+    vec![left, bottom, right, top]
+}
+
+/// Convert a position in mm from the top left corner of the page to PDF coordinates, which
+/// start at the bottom left corner of the page
+fn pdf_point(x: Mm, y: Mm, page_size: Size) -> (Object, Object) {
+    let x = x.0 * MM_TO_PT;
+    let y = (page_size.height.0 - y.0) * MM_TO_PT;
+    (Object::Real(x as f32), Object::Real(y as f32))
+}
+
+fn add_annotation(
+    doc: &mut lopdf::Document,
+    page_id: ObjectId,
+    annotation: ObjectId,
+) -> anyhow::Result<()> {
+    // The annotations array can either be stored directly in the page or as separate object
+    let annots_ref = match doc.get_dictionary(page_id)?.get(b"Annots") {
+        Ok(Object::Reference(id)) => Some(*id),
+        _ => None,
+    };
+
+    let annots = match annots_ref {
+        Some(id) => doc.get_object_mut(id)?,
+        None => {
+            let page = doc.get_dictionary_mut(page_id)?;
+            if !page.has(b"Annots") {
+                page.set("Annots", Vec::<Object>::new());
+            }
+            page.get_mut(b"Annots")?
+        }
+    };
+    annots.as_array_mut()?.push(annotation.into());
+
+    Ok(())
+}
@@ -0,0 +1,486 @@
+use std::{collections::VecDeque, rc::Rc};
+
+use genpdf::{
+    elements::Paragraph,
+    error::Error,
+    fonts::Metrics,
+    render,
+    style::{Color, LineStyle, Style, StyledString},
+    Alignment, Context, Element, Mm, Position, RenderResult, Size,
+};
+use hyphenation::Hyphenator as _;
+
+use crate::{
+    language::Hyphenator,
+    tracking::{LinkTarget, RenderTracker},
+};
+
+/// Conversion factor from typographic points (used for font sizes) to millimeters
+const PT_TO_MM: f64 = 25.4 / 72.0;
+
+/// A part of the text of a [`TextBlock`] with a single style
+#[derive(Debug, Clone)]
+pub struct TextRun {
+    pub text: StyledString,
+    /// Where the run links to. The link annotation covers the rendered run
+    pub link: Option<LinkTarget>,
+    /// Draw a line below the run
+    pub underline: bool,
+    /// Color of a box that is drawn behind the run
+    pub background: Option<Color>,
+    /// Language of a span in another language (`[Wort]{lang=de}`). The run is hyphenated with
+    /// the dictionary of the language instead of the one of the paragraph
+    pub lang: Option<Rc<Hyphenator>>,
+}
+
+impl TextRun {
+    pub fn new(text: StyledString) -> Self {
+        Self {
+            text,
+            link: None,
+            underline: false,
+            background: None,
+            lang: None,
+        }
+    }
+
+    /// Runs that need to know where they end up on the page
+    fn is_decorated(&self) -> bool {
+        self.link.is_some() || self.underline || self.background.is_some() || self.lang.is_some()
+    }
+}
+
+/// A paragraph made of text runs that can be links, be underlined, have a background or be in
+/// another language.
+///
+/// genpdf doesn't report where the text of a `Paragraph` ends up and hyphenates all of it with
+/// one dictionary, so text blocks with decorated runs are broken into lines here. Every rendered
+/// run is placed explicitly, so the underline, background, link annotation and language can be
+/// limited to it. Text blocks without decorated runs are rendered as normal `Paragraph`.
+pub struct TextBlock {
+    runs: Vec<TextRun>,
+    alignment: Alignment,
+    tracker: RenderTracker,
+    /// Created when the block is rendered for the first time
+    layout: Option<Layout>,
+}
+
+enum Layout {
+    Paragraph(Paragraph),
+    /// The words that are not rendered yet
+    Words(VecDeque<Word>),
+}
+
+impl TextBlock {
+    pub fn new(tracker: &RenderTracker) -> Self {
+        Self {
+            runs: Vec::new(),
+            alignment: Alignment::Left,
+            tracker: tracker.clone(),
+            layout: None,
+        }
+    }
+
+    pub fn set_alignment(&mut self, alignment: Alignment) {
+        self.alignment = alignment;
+    }
+
+    pub fn push(&mut self, run: TextRun) {
+        self.runs.push(run);
+    }
+
+    /// Push a space, unless the text is empty or already ends with a space
+    pub fn push_space(&mut self, run: TextRun) {
+        if self.runs.last().map(|it| !it.text.s.ends_with(' ')).unwrap_or(false) {
+            self.runs.push(run);
+        }
+    }
+
+    pub fn runs(&self) -> &[TextRun] {
+        &self.runs
+    }
+
+    fn make_paragraph(&self) -> Paragraph {
+        let mut p = Paragraph::default();
+        p.set_alignment(self.alignment);
+        for run in &self.runs {
+            p.push(run.text.clone());
+        }
+        p
+    }
+}
+
+impl Element for TextBlock {
+    fn render(
+        &mut self,
+        context: &Context,
+        mut area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        if self.layout.is_none() {
+            self.layout = Some(match self.runs.iter().any(TextRun::is_decorated) {
+                true => Layout::Words(split_words(&self.runs)),
+                false => Layout::Paragraph(self.make_paragraph()),
+            });
+        }
+        let words = match self.layout.as_mut().unwrap() {
+            Layout::Paragraph(p) => return p.render(context, area, style),
+            Layout::Words(words) => words,
+        };
+
+        let lines = LineLayout {
+            runs: &self.runs,
+            context,
+            style,
+            alignment: self.alignment,
+            tracker: &self.tracker,
+        };
+        let mut result = RenderResult::default();
+        while !words.is_empty() {
+            let line = lines.next_line(words, area.size().width);
+            let metrics = lines.metrics(&line);
+
+            // genpdf fails if nothing is rendered on an empty page, so a line that is higher
+            // than the page is rendered anyway
+            let page_start = result.size.height == Mm(0.0)
+                && self.tracker.is_page_start(area.size().height);
+            if metrics.line_height > area.size().height && !page_start {
+                line.restore(words);
+                result.has_more = true;
+                break;
+            }
+
+            lines.render_line(&area, &line, metrics, words.is_empty())?;
+            result.size.height += metrics.line_height;
+            area.add_offset(Position::new(0, metrics.line_height));
+        }
+
+        result.size.width = area.size().width;
+        Ok(result)
+    }
+}
+
+/// The part of a word that belongs to a single run
+#[derive(Debug, Clone)]
+struct Fragment {
+    text: String,
+    run: usize,
+}
+
+/// Text between two spaces. A word can consist of multiple runs, like "**bold**face"
+#[derive(Debug, Clone)]
+struct Word {
+    fragments: Vec<Fragment>,
+    /// The run of the space in front of the word, `None` for the first word
+    space: Option<usize>,
+}
+
+impl Word {
+    /// Split the word at the byte index for hyphenation, the first part gets the hyphen
+    fn split_at(&self, index: usize) -> (Word, Word) {
+        let mut head = Word {
+            fragments: Vec::new(),
+            space: self.space,
+        };
+        let mut tail = Word {
+            fragments: Vec::new(),
+            space: None,
+        };
+
+        let mut start = 0;
+        for fragment in &self.fragments {
+            let end = start + fragment.text.len();
+            if end <= index {
+                head.fragments.push(fragment.clone());
+            } else if start >= index {
+                tail.fragments.push(fragment.clone());
+            } else {
+                let (first, second) = fragment.text.split_at(index - start);
+                head.fragments.push(Fragment {
+                    text: first.to_string(),
+                    run: fragment.run,
+                });
+                tail.fragments.push(Fragment {
+                    text: second.to_string(),
+                    run: fragment.run,
+                });
+            }
+            start = end;
+        }
+
+        if let Some(last) = head.fragments.last_mut() {
+            last.text.push('-');
+        }
+        (head, tail)
+    }
+}
+
+/// Split the text of the runs into words. Consecutive spaces are collapsed and spaces at the
+/// start and the end of the text are dropped, like in a genpdf `Paragraph`
+fn split_words(runs: &[TextRun]) -> VecDeque<Word> {
+    let mut words = VecDeque::new();
+    let mut fragments = Vec::new();
+    let mut space = None;
+
+    for (index, run) in runs.iter().enumerate() {
+        for (i, part) in run.text.s.split(' ').enumerate() {
+            if i > 0 {
+                if !fragments.is_empty() {
+                    words.push_back(Word {
+                        fragments: std::mem::take(&mut fragments),
+                        space: space.take(),
+                    });
+                }
+                if !words.is_empty() && space.is_none() {
+                    space = Some(index);
+                }
+            }
+            if !part.is_empty() {
+                fragments.push(Fragment {
+                    text: part.to_string(),
+                    run: index,
+                });
+            }
+        }
+    }
+    if !fragments.is_empty() {
+        words.push_back(Word { fragments, space });
+    }
+
+    words
+}
+
+/// The words of a line, see [`LineLayout::next_line`]
+struct Line {
+    words: Vec<Word>,
+    /// The word that was hyphenated at the end of the line
+    split: Option<Word>,
+}
+
+impl Line {
+    /// Put the words back in front of the remaining words, if the line doesn't fit on the page
+    fn restore(self, words: &mut VecDeque<Word>) {
+        let mut line_words = self.words;
+        if let Some(word) = self.split {
+            words.pop_front();
+            line_words.pop();
+            line_words.push(word);
+        }
+        for word in line_words.into_iter().rev() {
+            words.push_front(word);
+        }
+    }
+}
+
+/// A run that is placed on a line. Spaces have no text, they are only placed so that the
+/// decorations of a run continue between its words
+struct Placement<'a> {
+    run: usize,
+    left: Mm,
+    width: Mm,
+    text: Option<&'a str>,
+}
+
+/// Breaks the words of a [`TextBlock`] into lines and renders them
+struct LineLayout<'a> {
+    runs: &'a [TextRun],
+    context: &'a Context,
+    style: Style,
+    alignment: Alignment,
+    tracker: &'a RenderTracker,
+}
+
+impl LineLayout<'_> {
+    fn run_style(&self, run: usize) -> Style {
+        self.style.and(self.runs[run].text.style)
+    }
+
+    fn text_width(&self, run: usize, text: &str) -> Mm {
+        self.run_style(run).str_width(&self.context.font_cache, text)
+    }
+
+    fn word_width(&self, word: &Word) -> Mm {
+        word.fragments.iter().map(|it| self.text_width(it.run, &it.text)).sum()
+    }
+
+    fn space_width(&self, word: &Word) -> Mm {
+        word.space.map(|run| self.text_width(run, " ")).unwrap_or(Mm(0.0))
+    }
+
+    /// Split the word at the last hyphenation point that fits into the width. Words in a span in
+    /// another language are hyphenated with its dictionary, other words with the hyphenator of
+    /// the render context
+    fn hyphenate(&self, word: &Word, width: Mm) -> Option<(Word, Word)> {
+        let dictionary = match &self.runs[word.fragments.first()?.run].lang {
+            Some(lang) => lang.dictionary(),
+            None => self.context.hyphenator.as_ref(),
+        }?;
+        let text: String = word.fragments.iter().map(|it| it.text.as_str()).collect();
+
+        dictionary
+            .hyphenate(&text)
+            .breaks
+            .into_iter()
+            .rev()
+            .map(|index| word.split_at(index))
+            .find(|(head, _)| self.word_width(head) <= width)
+    }
+
+    /// Take the words of the next line. A word that is wider than the line is hyphenated, or
+    /// put on its own line if that's not possible
+    fn next_line(&self, words: &mut VecDeque<Word>, width: Mm) -> Line {
+        let mut line = Line {
+            words: Vec::new(),
+            split: None,
+        };
+        let mut line_width = Mm(0.0);
+
+        while let Some(word) = words.pop_front() {
+            let space = match line.words.is_empty() {
+                true => Mm(0.0),
+                false => self.space_width(&word),
+            };
+            let word_width = self.word_width(&word);
+            if line_width + space + word_width <= width {
+                line_width += space + word_width;
+                line.words.push(word);
+                continue;
+            }
+
+            match self.hyphenate(&word, width - line_width - space) {
+                Some((head, tail)) => {
+                    line.words.push(head);
+                    words.push_front(tail);
+                    line.split = Some(word);
+                }
+                None if line.words.is_empty() => line.words.push(word),
+                None => words.push_front(word),
+            }
+            break;
+        }
+
+        line
+    }
+
+    /// The metrics of the largest font on the line, so that all runs share the baseline
+    fn metrics(&self, line: &Line) -> Metrics {
+        let font_cache = &self.context.font_cache;
+        line.words
+            .iter()
+            .flat_map(|word| &word.fragments)
+            .map(|fragment| self.run_style(fragment.run).metrics(font_cache))
+            .reduce(|max, metrics| max.max(&metrics))
+            .unwrap_or_else(|| self.style.metrics(font_cache))
+    }
+
+    fn place<'l>(&self, line: &'l Line, width: Mm, last_line: bool) -> Vec<Placement<'l>> {
+        let spaces: Mm = line.words.iter().skip(1).map(|it| self.space_width(it)).sum();
+        let words: Mm = line.words.iter().map(|it| self.word_width(it)).sum();
+        let free = (width - spaces - words).0;
+        let gaps = line.words.len().saturating_sub(1);
+
+        let (mut left, stretch) = match self.alignment {
+            Alignment::Center => (Mm(free / 2.0), Mm(0.0)),
+            Alignment::Right => (Mm(free), Mm(0.0)),
+            Alignment::Justified(_) if !last_line && gaps > 0 && free > 0.0 => {
+                (Mm(0.0), Mm(free / gaps as f64))
+            }
+            _ => (Mm(0.0), Mm(0.0)),
+        };
+
+        let mut placements = Vec::new();
+        for (i, word) in line.words.iter().enumerate() {
+            if let (true, Some(run)) = (i > 0, word.space) {
+                let width = self.space_width(word) + stretch;
+                placements.push(Placement {
+                    run,
+                    left,
+                    width,
+                    text: None,
+                });
+                left += width;
+            }
+            for fragment in &word.fragments {
+                let width = self.text_width(fragment.run, &fragment.text);
+                placements.push(Placement {
+                    run: fragment.run,
+                    left,
+                    width,
+                    text: Some(&fragment.text),
+                });
+                left += width;
+            }
+        }
+
+        placements
+    }
+
+    fn render_line(
+        &self,
+        area: &render::Area<'_>,
+        line: &Line,
+        metrics: Metrics,
+        last_line: bool,
+    ) -> Result<(), Error> {
+        let font_cache = &self.context.font_cache;
+        let placements = self.place(line, area.size().width, last_line);
+
+        // Neighbouring placements of the same run are decorated as one box
+        let mut boxes: Vec<(usize, Mm, Mm)> = Vec::new();
+        for placement in &placements {
+            let right = placement.left + placement.width;
+            match boxes.last_mut() {
+                Some((run, _, end)) if *run == placement.run => *end = right,
+                _ => boxes.push((placement.run, placement.left, right)),
+            }
+        }
+
+        // The backgrounds are drawn first, so that they don't cover the text
+        let middle = Mm(metrics.line_height.0 / 2.0);
+        for (run, left, right) in &boxes {
+            if let Some(color) = self.runs[*run].background {
+                area.draw_line(
+                    vec![Position::new(*left, middle), Position::new(*right, middle)],
+                    LineStyle::new().with_thickness(metrics.line_height).with_color(color),
+                );
+            }
+        }
+
+        for placement in &placements {
+            if let Some(text) = placement.text {
+                let position = Position::new(placement.left, 0);
+                if let Some(mut section) = area.text_section(font_cache, position, metrics) {
+                    section.print_str(text, self.run_style(placement.run))?;
+                }
+            }
+        }
+
+        for (run, left, right) in boxes {
+            let style = self.run_style(run);
+            if self.runs[run].underline {
+                let size = style.font_size() as f64 * PT_TO_MM;
+                let y = metrics.ascent + Mm(size * 0.12);
+                area.draw_line(
+                    vec![Position::new(left, y), Position::new(right, y)],
+                    LineStyle::new()
+                        .with_thickness(size * 0.05)
+                        .with_color(style.color().unwrap_or(Color::Rgb(0, 0, 0))),
+                );
+            }
+
+            let rect = self.tracker.locate(
+                area.size(),
+                Position::new(left, 0),
+                Size::new(right - left, metrics.line_height),
+            );
+            if let Some(target) = &self.runs[run].link {
+                self.tracker.add_link_area(rect, target.clone());
+            }
+            if let Some(lang) = &self.runs[run].lang {
+                self.tracker.add_lang_area(rect, lang.lang());
+            }
+        }
+
+        Ok(())
+    }
+}
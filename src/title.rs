@@ -0,0 +1,206 @@
+use clap::ValueEnum;
+use genpdf::{
+    elements::{LinearLayout, PaddedElement, PageBreak, Paragraph, TableLayout},
+    style::{Style, StyledString},
+    Alignment, Document, Margins,
+};
+use serde::Deserialize;
+
+use crate::{base_style::DocumentStyle, glyph_fallback::GlyphFallback};
+
+/// How the title, authors, date and abstract are placed, similar to the LaTeX document classes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TitleLayout {
+    /// Title block at the top of the first page, followed by the abstract and the text
+    Article,
+    /// Title on its own page, followed by the abstract on its own page
+    Report,
+    /// No title block, the title is only used for the PDF metadata
+    None,
+}
+
+/// An author of the document. In the frontmatter this is either just the name, or a map with
+/// `name`, `affiliation` and `email`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "AuthorConfig")]
+pub struct Author {
+    pub name: String,
+    pub affiliation: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AuthorConfig {
+    Name(String),
+    Full {
+        name: String,
+        affiliation: Option<String>,
+        email: Option<String>,
+    },
+}
+
+impl From<AuthorConfig> for Author {
+    fn from(value: AuthorConfig) -> Self {
+        match value {
+            AuthorConfig::Name(name) => Self {
+                name,
+                affiliation: None,
+                email: None,
+            },
+            AuthorConfig::Full {
+                name,
+                affiliation,
+                email,
+            } => Self {
+                name,
+                affiliation,
+                email,
+            },
+        }
+    }
+}
+
+/// Maximum number of authors that are placed next to each other
+const AUTHORS_PER_ROW: usize = 3;
+
+/// Push the title block to the start of the document, if the document has a title
+pub fn push_title(doc: &mut Document, docstyle: &DocumentStyle, fallback: &GlyphFallback) {
+    let layout = docstyle.title_block_layout();
+    if layout == TitleLayout::None {
+        return;
+    }
+
+    let mut block = LinearLayout::vertical();
+    let title_style = Style::new().with_font_size(docstyle.h2_size);
+    block.push(centered(&docstyle.title, title_style, fallback));
+
+    if let Some(subtitle) = &docstyle.subtitle {
+        let style = Style::new().with_font_size(docstyle.h4_size);
+        block.push(PaddedElement::new(
+            centered(subtitle, style, fallback),
+            Margins::trbl(2, 0, 0, 0),
+        ));
+    }
+
+    if !docstyle.authors.is_empty() {
+        block.push(PaddedElement::new(
+            make_authors(&docstyle.authors, docstyle, fallback),
+            Margins::trbl(8, 0, 0, 0),
+        ));
+    }
+
+    if let Some(date) = &docstyle.date {
+        let style = Style::new().with_font_size(docstyle.text_size.saturating_add(1));
+        block.push(PaddedElement::new(
+            centered(date, style, fallback),
+            Margins::trbl(4, 0, 0, 0),
+        ));
+    }
+
+    match layout {
+        TitleLayout::Article => {
+            doc.push(PaddedElement::new(block, Margins::trbl(0, 0, 10, 0)));
+            if let Some(text) = &docstyle.abstract_text {
+                doc.push(PaddedElement::new(
+                    make_abstract(text, docstyle, fallback),
+                    Margins::trbl(0, 0, 8, 0),
+                ));
+            }
+        }
+        TitleLayout::Report => {
+            doc.push(PaddedElement::new(block, Margins::trbl(50, 0, 0, 0)));
+            doc.push(PageBreak::new());
+            if let Some(text) = &docstyle.abstract_text {
+                doc.push(PaddedElement::new(
+                    make_abstract(text, docstyle, fallback),
+                    Margins::trbl(30, 0, 0, 0),
+                ));
+                doc.push(PageBreak::new());
+            }
+        }
+        TitleLayout::None => (),
+    }
+}
+
+fn centered(text: &str, style: Style, fallback: &GlyphFallback) -> Paragraph {
+    let mut p = Paragraph::default();
+    for (run, style) in fallback.split(text, style) {
+        p.push(StyledString::new(run, style));
+    }
+    p.set_alignment(Alignment::Center);
+    p
+}
+
+/// The authors next to each other, each with the affiliation and email below the name
+fn make_authors(
+    authors: &[Author],
+    docstyle: &DocumentStyle,
+    fallback: &GlyphFallback,
+) -> TableLayout {
+    let columns = authors.len().min(AUTHORS_PER_ROW);
+    let mut table = TableLayout::new(vec![1; columns]);
+
+    for (i, authors) in authors.chunks(columns).enumerate() {
+        let mut row = table.row();
+        for author in authors {
+            let mut cell = LinearLayout::vertical();
+            let name_style = Style::new().with_font_size(docstyle.text_size.saturating_add(1));
+            cell.push(centered(&author.name, name_style, fallback));
+            if let Some(affiliation) = &author.affiliation {
+                let style = Style::new().with_font_size(docstyle.text_size).italic();
+                cell.push(centered(affiliation, style, fallback));
+            }
+            if let Some(email) = &author.email {
+                let style =
+                    Style::new().with_font_size(docstyle.text_size.saturating_sub(1).max(1));
+                cell.push(centered(email, style, fallback));
+            }
+
+            let top = if i == 0 { 0 } else { 4 };
+            row.push_element(PaddedElement::new(cell, Margins::trbl(top, 0, 0, 0)));
+        }
+        // Every row needs one element per column
+        for _ in authors.len()..columns {
+            row.push_element(Paragraph::default());
+        }
+        row.push().expect("Invalid number of authors in a row");
+    }
+
+    table
+}
+
+/// The abstract with a centered heading, indented on both sides like in LaTeX
+fn make_abstract(
+    text: &str,
+    docstyle: &DocumentStyle,
+    fallback: &GlyphFallback,
+) -> PaddedElement<LinearLayout> {
+    let font_size = docstyle.text_size.saturating_sub(1).max(1);
+    let mut block = LinearLayout::vertical();
+
+    let heading_style = Style::new().with_font_size(font_size).bold();
+    block.push(PaddedElement::new(
+        centered(&docstyle.abstract_title, heading_style, fallback),
+        Margins::trbl(0, 0, 2, 0),
+    ));
+
+    // Paragraphs are separated by empty lines, like in markdown
+    for paragraph in text.split("\n\n").filter(|it| !it.trim().is_empty()) {
+        let text = paragraph.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut p = Paragraph::default();
+        for (run, style) in fallback.split(&text, Style::new().with_font_size(font_size)) {
+            p.push(StyledString::new(run, style));
+        }
+        if docstyle.align_justify {
+            p.set_alignment(Alignment::Justified(true));
+        }
+        block.push(PaddedElement::new(
+            p,
+            Margins::trbl(0, 0, docstyle.paragraph_spacing, 0),
+        ));
+    }
+
+    PaddedElement::new(block, Margins::trbl(0, 12, 0, 12))
+}
@@ -0,0 +1,67 @@
+use genpdf::{
+    elements::{LinearLayout, PaddedElement, Paragraph},
+    style::{Style, StyledString},
+    Element, Margins, Mm,
+};
+
+use crate::{
+    base_style::DocumentStyle,
+    elements::TocEntry,
+    glyph_fallback::GlyphFallback,
+    headings::Heading,
+    tracking::{LayoutInfo, LinkTarget, RenderTracker},
+};
+
+/// Paragraphs that only contain this marker are replaced by the table of contents
+pub const TOC_MARKER: &str = "[[toc]]";
+
+/// Create the table of contents for all headings up to the configured level. The page numbers
+/// are taken from the layout of the previous render pass, so in the first pass they are unknown.
+pub fn make_toc(
+    docstyle: &DocumentStyle,
+    headings: &[Heading],
+    layout: Option<&LayoutInfo>,
+    tracker: &RenderTracker,
+    glyph_fallback: &GlyphFallback,
+) -> LinearLayout {
+    let mut toc = LinearLayout::vertical();
+
+    let mut title_style = Style::new();
+    title_style.set_bold();
+    title_style.set_font_size(docstyle.get_header_size(2));
+    let mut title = Paragraph::default();
+    for (run, style) in glyph_fallback.split(&docstyle.toc_title, title_style) {
+        title.push(StyledString::new(run, style));
+    }
+    toc.push(PaddedElement::new(
+        title,
+        Margins::trbl(docstyle.header_spacing, 0, docstyle.header_spacing, 0),
+    ));
+
+    for heading in headings.iter().filter(|h| h.level <= docstyle.toc_depth) {
+        let page = layout
+            .and_then(|layout| layout.anchor_pages.get(&heading.anchor))
+            .map(|page| page.to_string())
+            .unwrap_or_else(|| "?".to_string());
+
+        let indent = Mm(f64::from(heading.level - 1) * 5.0);
+        let entry = TocEntry::new(heading.numbered_text(), page, indent, glyph_fallback);
+
+        let (entry, id) = tracker.track(entry);
+        tracker.add_link(id, LinkTarget::Anchor(heading.anchor.clone()));
+
+        // Top level entries are bold and a bit separated from the previous entries
+        let mut entry_style = Style::new();
+        let mut top_margin = 0.0;
+        if heading.level == 1 {
+            entry_style.set_bold();
+            top_margin = docstyle.paragraph_spacing;
+        }
+        toc.push(PaddedElement::new(
+            entry.styled(entry_style),
+            Margins::trbl(top_margin, 0, 0, 0),
+        ));
+    }
+
+    toc
+}
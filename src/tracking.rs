@@ -0,0 +1,347 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use genpdf::{
+    error::Error, render, style::Style, Context, Element, Margins, Mm, PageDecorator, Position,
+    RenderResult, Size,
+};
+
+/// Identifies an element that was wrapped with [`RenderTracker::track`]
+pub type TrackId = usize;
+
+/// An area on a rendered page. The coordinates are in mm, relative to the top left corner of the
+/// page. Pages are counted starting at 1.
+#[derive(Debug, Clone, Copy)]
+pub struct PageRect {
+    pub page: usize,
+    pub left: Mm,
+    pub top: Mm,
+    pub right: Mm,
+    pub bottom: Mm,
+}
+
+/// Where a link annotation leads to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkTarget {
+    /// External link that is opened by the PDF viewer
+    Uri(String),
+    /// Internal link to an element that was registered with [`RenderTracker::add_anchor`]
+    Anchor(String),
+}
+
+/// Layout information of a finished render pass. Content that depends on the final layout, like
+/// the page numbers in the table of contents, is created from the layout of the previous pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LayoutInfo {
+    pub page_count: usize,
+    /// The page every anchor was rendered to
+    pub anchor_pages: HashMap<String, usize>,
+    /// Pages that start with an orientation break, with the orientation of the page
+    pub orientation_pages: HashMap<usize, bool>,
+    /// Empty pages that were inserted so that a chapter starts on a right-hand page
+    pub blank_pages: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct TrackerState {
+    /// The page that is currently rendered
+    page: usize,
+    /// Margins of the current page, set by the page decorator that applies them
+    margins: Margins,
+    /// Right edge of the content area of the current page
+    content_right: Mm,
+    /// Bottom edge of the content area of the current page
+    content_bottom: Mm,
+    /// Distance between the right edge of the current column and the right edge of the content
+    right_inset: Mm,
+    /// The rendered areas of every tracked element, indexed by its `TrackId`
+    areas: Vec<Vec<PageRect>>,
+    links: Vec<(TrackId, LinkTarget)>,
+    /// Links that cover a located area instead of a tracked element, see
+    /// [`RenderTracker::add_link_area`]
+    link_areas: Vec<(PageRect, LinkTarget)>,
+    /// Areas with text in another language than the document, with the language tag
+    lang_areas: Vec<(PageRect, String)>,
+    anchors: HashMap<String, TrackId>,
+    /// Whether the following pages should be in landscape orientation
+    landscape: bool,
+    /// The portrait pages whose content was laid out in landscape orientation
+    rotated_pages: Vec<usize>,
+    /// Pages that start with an orientation break, see [`LayoutInfo::orientation_pages`]
+    orientation_pages: HashMap<usize, bool>,
+    /// See [`LayoutInfo::blank_pages`]
+    blank_pages: Vec<usize>,
+}
+
+/// Collects information about where elements end up in the rendered document. The pages are
+/// counted by a [`TrackingDecorator`] and the positions are recorded by the [`Tracked`] elements.
+///
+/// genpdf doesn't expose the absolute position of an area, but every area that is handed to an
+/// element extends to the bottom and the right edge of the page content. So the position can be
+/// calculated from the remaining size of the area. Only areas in columns end before the right
+/// edge, which is corrected with [`RenderTracker::inset_right`].
+#[derive(Debug, Clone)]
+pub struct RenderTracker(Rc<RefCell<TrackerState>>);
+
+impl RenderTracker {
+    pub fn new() -> Self {
+        Self(Rc::new(RefCell::new(TrackerState {
+            page: 0,
+            margins: Margins::trbl(0, 0, 0, 0),
+            content_right: Mm(0.0),
+            content_bottom: Mm(0.0),
+            right_inset: Mm(0.0),
+            areas: Vec::new(),
+            links: Vec::new(),
+            link_areas: Vec::new(),
+            lang_areas: Vec::new(),
+            anchors: HashMap::new(),
+            landscape: false,
+            rotated_pages: Vec::new(),
+            orientation_pages: HashMap::new(),
+            blank_pages: Vec::new(),
+        })))
+    }
+
+    /// Wrap the element so that the areas it is rendered to are recorded
+    pub fn track<E: Element>(&self, element: E) -> (Tracked<E>, TrackId) {
+        let mut state = self.0.borrow_mut();
+        let id = state.areas.len();
+        state.areas.push(Vec::new());
+
+        let tracked = Tracked {
+            element,
+            id,
+            tracker: self.clone(),
+        };
+
+        (tracked, id)
+    }
+
+    /// Add a link annotation that covers the area of the tracked element
+    pub fn add_link(&self, id: TrackId, target: LinkTarget) {
+        self.0.borrow_mut().links.push((id, target));
+    }
+
+    /// Add a link annotation that covers the given area, for links inside of an element. The
+    /// area can be calculated with [`RenderTracker::locate`]
+    pub fn add_link_area(&self, rect: PageRect, target: LinkTarget) {
+        self.0.borrow_mut().link_areas.push((rect, target));
+    }
+
+    /// Record that the text in the area is in the language with the given tag
+    pub fn add_lang_area(&self, rect: PageRect, lang: &str) {
+        self.0.borrow_mut().lang_areas.push((rect, lang.to_string()));
+    }
+
+    pub fn lang_areas(&self) -> Vec<(PageRect, String)> {
+        self.0.borrow().lang_areas.clone()
+    }
+
+    /// Calculate where a part of an area ends up on the current page. `available` is the size of
+    /// the area that is handed to the element, `offset` and `size` describe the part of it.
+    pub fn locate(&self, available: Size, offset: Position, size: Size) -> PageRect {
+        let state = self.0.borrow();
+        let left = state.content_right - state.right_inset - available.width + offset.x;
+        let top = state.content_bottom - available.height + offset.y;
+
+        PageRect {
+            page: state.page,
+            left,
+            top,
+            right: left + size.width,
+            bottom: top + size.height,
+        }
+    }
+
+    /// Register the tracked element as target for internal links with the given name
+    pub fn add_anchor(&self, id: TrackId, name: &str) {
+        self.0.borrow_mut().anchors.insert(name.to_string(), id);
+    }
+
+    /// The first area the anchor element was rendered to
+    pub fn anchor_area(&self, name: &str) -> Option<PageRect> {
+        let state = self.0.borrow();
+        let id = state.anchors.get(name)?;
+        state.areas[*id].first().copied()
+    }
+
+    /// Set the margins of the page that is decorated. Since the margins can differ between pages
+    /// (two-sided layout), they are reported by the decorator that applies them.
+    pub fn set_page_margins(&self, margins: Margins) {
+        self.0.borrow_mut().margins = margins;
+    }
+
+    /// Check if an element with the given available height would be the first element on the
+    /// current page
+    pub fn is_page_start(&self, available_height: Mm) -> bool {
+        let state = self.0.borrow();
+        let top = state.content_bottom - available_height;
+        top.0 - state.margins.top.0 < 0.01
+    }
+
+    /// The page that is currently rendered, counted starting at 1
+    pub fn current_page(&self) -> usize {
+        self.0.borrow().page
+    }
+
+    /// Add the distance of the column that is rendered to the right edge of its area. Areas in
+    /// columns don't extend to the right edge of the content, so the position of tracked elements
+    /// has to be corrected by this distance. The previous distance is restored when the returned
+    /// guard is dropped, also if the rendering of the column fails.
+    pub fn inset_right(&self, inset: Mm) -> RightInsetGuard {
+        let mut state = self.0.borrow_mut();
+        let previous = state.right_inset;
+        state.right_inset = previous + inset;
+
+        RightInsetGuard {
+            tracker: self.clone(),
+            previous,
+        }
+    }
+
+    /// Set the orientation of the pages that are started from now on
+    pub fn set_landscape(&self, landscape: bool) {
+        self.0.borrow_mut().landscape = landscape;
+    }
+
+    pub fn is_landscape(&self) -> bool {
+        self.0.borrow().landscape
+    }
+
+    /// Record that the content of the page was laid out in landscape orientation, so that the
+    /// page is rotated while postprocessing
+    pub fn add_rotated_page(&self, page: usize) {
+        self.0.borrow_mut().rotated_pages.push(page);
+    }
+
+    pub fn rotated_pages(&self) -> Vec<usize> {
+        self.0.borrow().rotated_pages.clone()
+    }
+
+    /// Record that the current page starts with a switch to the given orientation. The page was
+    /// already decorated, so the orientation can only be applied in the next render pass
+    pub fn add_orientation_page(&self, landscape: bool) {
+        let mut state = self.0.borrow_mut();
+        let page = state.page;
+        state.orientation_pages.insert(page, landscape);
+    }
+
+    /// Record that the current page is left empty. It was already decorated, so the header and
+    /// footer can only be left out in the next render pass
+    pub fn add_blank_page(&self) {
+        let mut state = self.0.borrow_mut();
+        let page = state.page;
+        state.blank_pages.push(page);
+    }
+
+    pub fn layout_info(&self) -> LayoutInfo {
+        let state = self.0.borrow();
+        let anchor_pages = state
+            .anchors
+            .iter()
+            .filter_map(|(name, id)| Some((name.clone(), state.areas[*id].first()?.page)))
+            .collect();
+
+        LayoutInfo {
+            page_count: state.page,
+            anchor_pages,
+            orientation_pages: state.orientation_pages.clone(),
+            blank_pages: state.blank_pages.clone(),
+        }
+    }
+
+    pub fn links(&self) -> Vec<(Vec<PageRect>, LinkTarget)> {
+        let state = self.0.borrow();
+        let element_links = state
+            .links
+            .iter()
+            .map(|(id, target)| (state.areas[*id].clone(), target.clone()));
+        let area_links = state
+            .link_areas
+            .iter()
+            .map(|(rect, target)| (vec![*rect], target.clone()));
+
+        element_links.chain(area_links).collect()
+    }
+
+    /// Wrap the page decorator so that the pages are counted
+    pub fn decorator<D: PageDecorator>(&self, inner: D) -> TrackingDecorator<D> {
+        TrackingDecorator {
+            inner,
+            tracker: self.clone(),
+        }
+    }
+}
+
+/// Restores the right inset of the tracker when dropped, see [`RenderTracker::inset_right`]
+pub struct RightInsetGuard {
+    tracker: RenderTracker,
+    previous: Mm,
+}
+
+impl Drop for RightInsetGuard {
+    fn drop(&mut self) {
+        self.tracker.0.borrow_mut().right_inset = self.previous;
+    }
+}
+
+impl Default for RenderTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An element that records the areas it was rendered to. See [`RenderTracker::track`]
+pub struct Tracked<E: Element> {
+    element: E,
+    id: TrackId,
+    tracker: RenderTracker,
+}
+
+impl<E: Element> Element for Tracked<E> {
+    fn render(
+        &mut self,
+        context: &Context,
+        area: render::Area<'_>,
+        style: Style,
+    ) -> Result<RenderResult, Error> {
+        let available = area.size();
+        // The tracker state must not be borrowed while rendering, since the element could contain
+        // other tracked elements
+        let result = self.element.render(context, area, style)?;
+
+        if result.size.height > Mm(0.0) {
+            let rect = self.tracker.locate(available, Position::new(0, 0), result.size);
+            self.tracker.0.borrow_mut().areas[self.id].push(rect);
+        }
+
+        Ok(result)
+    }
+}
+
+/// Page decorator that counts the rendered pages for the [`RenderTracker`]
+pub struct TrackingDecorator<D: PageDecorator> {
+    inner: D,
+    tracker: RenderTracker,
+}
+
+impl<D: PageDecorator> PageDecorator for TrackingDecorator<D> {
+    fn decorate_page<'a>(
+        &mut self,
+        context: &Context,
+        area: render::Area<'a>,
+        style: Style,
+    ) -> Result<render::Area<'a>, Error> {
+        let area = self.inner.decorate_page(context, area, style)?;
+        let content_size = area.size();
+
+        // The inner decorator could reserve space at the bottom of the page (e.g. for footnotes),
+        // so the content edges are taken from the remaining area instead of the page size
+        let mut state = self.tracker.0.borrow_mut();
+        state.page += 1;
+        state.content_right = state.margins.left + content_size.width;
+        state.content_bottom = state.margins.top + content_size.height;
+
+        Ok(area)
+    }
+}